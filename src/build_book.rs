@@ -0,0 +1,75 @@
+use std::fs;
+
+use sqlite::{Connection, State};
+
+use crate::engine::{
+    opening_book::builder::{BookBuilder, DEFAULT_MAX_PLIES, DEFAULT_MIN_GAMES},
+    DB_PATH,
+};
+
+const USAGE: &str = "Usage: chess_engine build-book [--plies N] [--min-games N] \
+[--output FILE] [--polyglot FILE] [--replace] GAMES.pgn...";
+
+/// Builds an opening book from the PGN files named in `args`. An existing sqlite book is extended
+/// with the new games unless `--replace` is given.
+pub fn run(args: impl Iterator<Item = String>) -> Result<(), String> {
+    let mut max_plies = DEFAULT_MAX_PLIES;
+    let mut min_games = DEFAULT_MIN_GAMES;
+    let mut output = DB_PATH.to_owned();
+    let mut polyglot_output = None;
+    let mut replace = false;
+    let mut pgn_files = Vec::new();
+
+    let mut args = args;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--plies" => max_plies = number(args.next())?,
+            "--min-games" => min_games = number(args.next())?,
+            "--output" => output = args.next().ok_or(USAGE)?,
+            "--polyglot" => polyglot_output = Some(args.next().ok_or(USAGE)?),
+            "--replace" => replace = true,
+            _ if arg.starts_with("--") => return Err(USAGE.to_owned()),
+            _ => pgn_files.push(arg),
+        }
+    }
+    if pgn_files.is_empty() {
+        return Err(USAGE.to_owned());
+    }
+
+    let db_conn = Connection::open(&output).map_err(|e| format!("{output}: {e}"))?;
+    let mut builder = BookBuilder::new(max_plies, min_games);
+    // A new database has no moves table to extend yet. Anything else that keeps the book from
+    // loading stops the build, as writing it would replace the book with what was read so far.
+    let extend = !replace && has_moves_table(&db_conn).map_err(|e| format!("{output}: {e}"))?;
+    if extend {
+        builder
+            .load_sqlite(&db_conn)
+            .map_err(|e| format!("{output}: {e}"))?;
+    }
+
+    for file in &pgn_files {
+        let pgn = fs::read_to_string(file).map_err(|e| format!("{file}: {e}"))?;
+        println!("{file}: {} games", builder.add_pgn(&pgn));
+    }
+
+    let written = builder
+        .write_sqlite(&db_conn)
+        .map_err(|e| format!("{output}: {e}"))?;
+    println!("{output}: {written} moves");
+    if let Some(path) = polyglot_output {
+        let book = builder.polyglot();
+        book.save(&path).map_err(|e| format!("{path}: {e}"))?;
+        println!("{path}: {} entries", book.len());
+    }
+    Ok(())
+}
+
+fn has_moves_table(db_conn: &Connection) -> Result<bool, sqlite::Error> {
+    let mut stm = db_conn
+        .prepare("SELECT name FROM sqlite_master WHERE type = 'table' AND name = 'moves'")?;
+    Ok(matches!(stm.next()?, State::Row))
+}
+
+fn number<T: std::str::FromStr>(arg: Option<String>) -> Result<T, String> {
+    arg.and_then(|n| n.parse().ok()).ok_or(USAGE.to_owned())
+}
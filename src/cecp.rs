@@ -0,0 +1,297 @@
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+use chess_backend::{Board, Colour};
+
+use crate::{
+    engine::{
+        utils::{eval::Eval, lan::LanMove},
+        EngineController, SearchLimits,
+    },
+    ENGINE_NAME,
+};
+
+// A forced mate in N moves is reported as MATE_SCORE + N, and being mated as -MATE_SCORE - N,
+// following the xboard convention
+const MATE_SCORE: i32 = 100000;
+
+/// Runs the CECP (xboard) protocol on the given input lines until the GUI sends `quit`.
+/// The `xboard` command that selected the protocol is expected to have been consumed already.
+pub fn run(input: impl Iterator<Item = String>, controller: EngineController) {
+    let mut session = Session::new(controller);
+    for line in input {
+        if !session.handle(&line) {
+            break;
+        }
+    }
+    session.abandon_search();
+}
+
+/// The time control set by the GUI, and how much of it is left.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Clock {
+    // Zero means the whole game has to be played within the base time
+    moves_per_session: u32,
+    increment: Duration,
+    time_left: Duration,
+    time_per_move: Option<Duration>,
+    depth: Option<usize>,
+}
+impl Clock {
+    /// Limits for the search of the engine's next move, after it has played `moves_played`.
+    pub(crate) fn limits(&self, moves_played: u32) -> SearchLimits {
+        let mut limits = if let Some(time_per_move) = self.time_per_move {
+            SearchLimits::time(time_per_move)
+        } else {
+            let moves_to_go = if self.moves_per_session > 0 {
+                Some(self.moves_per_session - moves_played % self.moves_per_session)
+            } else {
+                None
+            };
+            SearchLimits::from_clock(self.time_left, self.increment, moves_to_go)
+        };
+        limits.depth = self.depth;
+        limits
+    }
+
+    /// Applies one of the commands that set the clock: level, st, sd and time. Arguments that do
+    /// not parse leave the clock as it was.
+    pub(crate) fn update(&mut self, command: &str, args: &str) {
+        match command {
+            "level" => self.level(args),
+            "st" => self.time_per_move = args.parse::<u64>().ok().map(Duration::from_secs),
+            "sd" => self.depth = args.parse().ok(),
+            "time" => {
+                if let Ok(centiseconds) = args.parse::<u64>() {
+                    self.time_left = Duration::from_millis(centiseconds * 10);
+                }
+            }
+            _ => (),
+        }
+    }
+
+    // level MPS BASE INC, where BASE is either minutes or minutes:seconds
+    fn level(&mut self, args: &str) {
+        let mut fields = args.split_whitespace();
+        let (Some(mps), Some(base), Some(inc)) = (fields.next(), fields.next(), fields.next())
+        else {
+            return;
+        };
+        let base = match base.split_once(':') {
+            Some((minutes, seconds)) => minutes
+                .parse::<u64>()
+                .ok()
+                .zip(seconds.parse::<u64>().ok())
+                .map(|(m, s)| m * 60 + s),
+            None => base.parse::<u64>().ok().map(|m| m * 60),
+        };
+
+        if let (Ok(mps), Some(base), Ok(inc)) = (mps.parse(), base, inc.parse::<f64>()) {
+            *self = Self {
+                moves_per_session: mps,
+                increment: Duration::from_secs_f64(inc.max(0.)),
+                time_left: Duration::from_secs(base),
+                time_per_move: None,
+                ..*self
+            };
+        }
+    }
+}
+impl Default for Clock {
+    fn default() -> Self {
+        Self {
+            moves_per_session: 40,
+            increment: Duration::ZERO,
+            time_left: Duration::from_secs(300),
+            time_per_move: None,
+            depth: None,
+        }
+    }
+}
+
+struct Session {
+    // The controller is moved to the search thread while the engine is thinking
+    controller: Option<EngineController>,
+    search: Option<JoinHandle<EngineController>>,
+    stop: Arc<AtomicBool>,
+    // Set when the move currently being thought about should not be played
+    abandon: Arc<AtomicBool>,
+    // None in force mode, where the engine only keeps track of the moves played
+    engine_side: Option<Colour>,
+    moves_played: u32,
+    post: bool,
+    clock: Clock,
+}
+impl Session {
+    fn new(controller: EngineController) -> Self {
+        Self {
+            stop: controller.stop_flag(),
+            controller: Some(controller),
+            search: None,
+            abandon: Arc::new(AtomicBool::new(false)),
+            engine_side: Some(Colour::Black),
+            moves_played: 0,
+            post: false,
+            clock: Clock::default(),
+        }
+    }
+
+    /// Handles a single line of input. Returns false once the session should end.
+    fn handle(&mut self, line: &str) -> bool {
+        let line = line.trim();
+        let (command, args) = line.split_once(' ').unwrap_or((line, ""));
+        match command {
+            "protover" => println!(
+                "feature myname=\"{ENGINE_NAME}\" usermove=1 setboard=1 ping=1 sigint=0 sigterm=0 colors=0 done=1"
+            ),
+            "new" => {
+                self.abandon_search();
+                let controller = self.controller();
+                controller.new_game();
+                self.engine_side = Some(Colour::Black);
+                self.moves_played = 0;
+                self.clock.depth = None;
+            }
+            "force" => {
+                self.abandon_search();
+                self.engine_side = None;
+            }
+            "go" => {
+                let side = self.controller().board().side_to_move();
+                self.engine_side = Some(side);
+                self.think();
+            }
+            "usermove" => self.usermove(args),
+            "setboard" => {
+                self.abandon_search();
+                self.controller().set_position(Board::from(args));
+            }
+            "undo" => {
+                self.abandon_search();
+                self.controller().undo();
+            }
+            "remove" => {
+                self.abandon_search();
+                self.controller().undo();
+                self.controller().undo();
+            }
+            "level" | "st" | "sd" | "time" => self.clock.update(command, args),
+            // result RESULT {COMMENT}, the book learns from finished games
+            "result" => {
+                self.abandon_search();
+                self.engine_side = None;
+                let winner = match args.split_whitespace().next() {
+                    Some("1-0") => Some(Some(Colour::White)),
+                    Some("0-1") => Some(Some(Colour::Black)),
+                    Some("1/2-1/2") => Some(None),
+                    // Unfinished games
+                    _ => None,
+                };
+                if let Some(winner) = winner {
+                    self.controller().game_over(winner);
+                }
+            }
+            "post" => self.post = true,
+            "nopost" => self.post = false,
+            "ping" => println!("pong {args}"),
+            // Move now
+            "?" => self.stop.store(true, Ordering::Relaxed),
+            "quit" => return false,
+            // xboard, otim, accepted, rejected, hard, easy, random, computer and the like
+            _ => (),
+        }
+        true
+    }
+
+    /// Waits for the engine to finish thinking and hands back the controller.
+    fn controller(&mut self) -> &mut EngineController {
+        if let Some(search) = self.search.take() {
+            self.controller = Some(search.join().expect("Search thread panicked"));
+        }
+        self.controller
+            .as_mut()
+            .expect("Controller is available when the engine is not thinking")
+    }
+
+    /// Stops thinking without playing the move that was being searched for.
+    fn abandon_search(&mut self) {
+        self.abandon.store(true, Ordering::Relaxed);
+        self.stop.store(true, Ordering::Relaxed);
+        self.controller();
+    }
+
+    fn usermove(&mut self, notation: &str) {
+        let legal = notation
+            .parse::<LanMove>()
+            .is_ok_and(|mv| self.controller().make_move(mv));
+        if legal {
+            self.think();
+        } else {
+            println!("Illegal move: {notation}");
+        }
+    }
+
+    /// Starts thinking if it is the engine's turn. The chosen move is played and sent to the GUI
+    /// once the search finishes.
+    fn think(&mut self) {
+        let Some(side) = self.engine_side else {
+            return;
+        };
+        if self.controller().board().side_to_move() != side {
+            return;
+        }
+
+        let mut controller = self.controller.take().unwrap();
+        let limits = self.clock.limits(self.moves_played);
+        self.moves_played += 1;
+        self.abandon.store(false, Ordering::Relaxed);
+        // Cleared here rather than in the search thread, so that a move now sent right away is
+        // kept
+        self.stop.store(false, Ordering::Relaxed);
+        let abandon = self.abandon.clone();
+        let post = self.post;
+
+        self.search = Some(thread::spawn(move || {
+            let res = controller.search(limits, |res| {
+                if post && !abandon.load(Ordering::Relaxed) {
+                    // ply score time nodes pv
+                    println!(
+                        "{} {} {} {} {}",
+                        res.depth,
+                        score(res.eval, side),
+                        res.pv.time.as_millis() / 10,
+                        res.pv.nodes,
+                        res.pv.san.join(" ")
+                    );
+                }
+            });
+            if abandon.load(Ordering::Relaxed) {
+                return controller;
+            }
+            if let Some(mv) = res.best_move {
+                controller.make_move(mv);
+                println!("move {mv}");
+            }
+            controller
+        }));
+    }
+}
+
+// Converts an evaluation to centipawns from the point of view of the engine
+fn score(eval: Option<Eval>, side: Colour) -> i32 {
+    let Some(eval) = eval else {
+        return 0;
+    };
+    match (eval.mate_in(side), side) {
+        (Some(moves), _) if moves > 0 => MATE_SCORE + moves,
+        (Some(moves), _) => -MATE_SCORE + moves,
+        (None, Colour::White) => eval.value(),
+        (None, Colour::Black) => -eval.value(),
+    }
+}
@@ -0,0 +1,115 @@
+use chess_backend::{Colour, Pieces};
+
+use crate::engine::{
+    utils::{
+        bitboard::{
+            bishop_attacks, bitboard, file_mask, king_attacks, knight_attacks, queen_attacks,
+            rank_mask, rook_attacks, squares,
+        },
+        phase::taper_centipawns,
+    },
+    zobrist::piece_lists,
+};
+
+// Own pawns in front of the king, by how many ranks they are ahead of it
+const SHIELD: [i32; 3] = [0, 20, 10];
+// Enemy pawns advancing on the king, by how many ranks they are ahead of it
+const STORM: [i32; 4] = [0, -15, -10, -5];
+// Files at or next to the king without own pawns, and without any pawns at all
+const HALF_OPEN_FILE: i32 = -15;
+const OPEN_FILE: i32 = -10;
+// How much an attack on a square of the king zone counts, by piece
+const KNIGHT_ATTACK: usize = 2;
+const BISHOP_ATTACK: usize = 2;
+const ROOK_ATTACK: usize = 3;
+const QUEEN_ATTACK: usize = 5;
+// The squares a piece attacks from a square, given the occupied squares
+type Attacks = fn(i32, u64) -> u64;
+
+// Danger by the summed weight of the attacks on the king zone, in hundredths of a pawn. It grows
+// much faster than the attacks, as defending becomes harder the more pieces join in.
+const SAFETY_TABLE: [u16; 50] = [
+    0, 0, 1, 2, 3, 5, 7, 9, 12, 15, 18, 22, 26, 30, 35, 39, 44, 50, 56, 62, 68, 75, 82, 85, 89, 97,
+    105, 113, 122, 131, 140, 150, 169, 180, 191, 202, 213, 225, 237, 248, 260, 272, 283, 295, 307,
+    319, 330, 342, 354, 366,
+];
+
+/// Safety of the kings in centipawns, from white's point of view. It counts the pawns sheltering
+/// each king and storming it, open files next to it and the pieces attacking the squares around
+/// it. As material comes off the kings become pieces like any other, so it fades out with the
+/// material phase.
+pub fn evaluate(white_pieces: &Pieces, black_pieces: &Pieces, material_phase: i32) -> i32 {
+    let occupied = [white_pieces, black_pieces]
+        .iter()
+        .flat_map(|pieces| piece_lists(pieces))
+        .fold(0, |occupied, squares| occupied | bitboard(squares));
+    let safety = king_safety(Colour::White, white_pieces, black_pieces, occupied)
+        - king_safety(Colour::Black, black_pieces, white_pieces, occupied);
+    taper_centipawns(safety, 0, material_phase)
+}
+
+fn king_safety(colour: Colour, own: &Pieces, enemy: &Pieces, occupied: u64) -> i32 {
+    let Some(&king) = own.king.first() else {
+        return 0;
+    };
+    let (file, rank) = (king % 8, king / 8);
+    // How many ranks `sq` is in front of the king
+    let ahead = |sq: i32| match colour {
+        Colour::White => sq / 8 - rank,
+        Colour::Black => rank - sq / 8,
+    };
+    let closest_ahead = |pawns: u64, f: i32| {
+        squares(pawns & file_mask(f))
+            .map(ahead)
+            .filter(|distance| *distance > 0)
+            .min()
+    };
+
+    let (own_pawns, enemy_pawns) = (bitboard(&own.pawns), bitboard(&enemy.pawns));
+    let mut res = 0;
+    for f in (file - 1).max(0)..=(file + 1).min(7) {
+        match closest_ahead(own_pawns, f) {
+            Some(distance) => res += SHIELD.get(distance as usize).unwrap_or(&0),
+            None => res += HALF_OPEN_FILE,
+        }
+        match closest_ahead(enemy_pawns, f) {
+            Some(distance) => res += STORM.get(distance as usize).unwrap_or(&0),
+            None if own_pawns & file_mask(f) == 0 => res += OPEN_FILE,
+            None => (),
+        }
+    }
+
+    // The squares around the king and the rank in front of those
+    let forward = match colour {
+        Colour::White => 1,
+        Colour::Black => -1,
+    };
+    let around = king_attacks(king) | 1 << king;
+    let zone = around
+        | squares(around)
+            .map(|sq| rank_mask(sq / 8 + forward) & file_mask(sq % 8))
+            .fold(0, |zone, sq| zone | sq);
+
+    let mut attackers = 0;
+    let mut weight = 0;
+    let attacks: [(&[i32], Attacks, usize); 4] = [
+        (&enemy.knights, |sq, _| knight_attacks(sq), KNIGHT_ATTACK),
+        (&enemy.bishops, bishop_attacks, BISHOP_ATTACK),
+        (&enemy.rooks, rook_attacks, ROOK_ATTACK),
+        (&enemy.queens, queen_attacks, QUEEN_ATTACK),
+    ];
+    for (pieces, piece_attacks, piece_weight) in attacks {
+        for sq in pieces {
+            let hits = (piece_attacks(*sq, occupied) & zone).count_ones() as usize;
+            if hits > 0 {
+                attackers += 1;
+                weight += piece_weight * hits;
+            }
+        }
+    }
+    // A lone attacker can seldom do much on its own
+    if attackers >= 2 {
+        res -= SAFETY_TABLE[weight.min(SAFETY_TABLE.len() - 1)] as i32;
+    }
+    res
+}
@@ -0,0 +1,54 @@
+use chess_backend::{Colour, Pieces};
+
+use crate::engine::{
+    utils::{
+        bitboard::{
+            bishop_attacks, bitboard, knight_attacks, pawn_attacks, queen_attacks, rook_attacks,
+        },
+        phase::taper_centipawns,
+    },
+    zobrist::piece_lists,
+};
+
+// Centipawns for every square a piece reaches, for the middlegame and the endgame, and the number
+// of squares that counts as average. Pieces with fewer squares than that are penalised.
+const KNIGHT_MOBILITY: (i32, i32, i32) = (4, 3, 4);
+const BISHOP_MOBILITY: (i32, i32, i32) = (5, 5, 6);
+const ROOK_MOBILITY: (i32, i32, i32) = (2, 4, 7);
+const QUEEN_MOBILITY: (i32, i32, i32) = (1, 2, 13);
+
+/// Mobility of the knights, bishops, rooks and queens of both sides in centipawns, from white's
+/// point of view.
+/// Squares are counted from the attacks of every piece, leaving out squares taken by own pieces
+/// and squares guarded by enemy pawns, where pieces would only be chased away.
+pub fn evaluate(white_pieces: &Pieces, black_pieces: &Pieces, material_phase: i32) -> i32 {
+    let occupancy = |pieces: &Pieces| {
+        piece_lists(pieces)
+            .iter()
+            .fold(0, |occupied, squares| occupied | bitboard(squares))
+    };
+    let (white, black) = (occupancy(white_pieces), occupancy(black_pieces));
+    let white_area = !white & !pawn_attacks(bitboard(&black_pieces.pawns), Colour::Black);
+    let black_area = !black & !pawn_attacks(bitboard(&white_pieces.pawns), Colour::White);
+
+    let (white_mg, white_eg) = side_mobility(white_pieces, white | black, white_area);
+    let (black_mg, black_eg) = side_mobility(black_pieces, white | black, black_area);
+    taper_centipawns(white_mg - black_mg, white_eg - black_eg, material_phase)
+}
+
+fn side_mobility(pieces: &Pieces, occupied: u64, area: u64) -> (i32, i32) {
+    let mut res = (0, 0);
+    let mut add =
+        |squares: &[i32], attacks: fn(i32, u64) -> u64, (mg, eg, average): (i32, i32, i32)| {
+            for sq in squares {
+                let count = (attacks(*sq, occupied) & area).count_ones() as i32 - average;
+                res.0 += mg * count;
+                res.1 += eg * count;
+            }
+        };
+    add(&pieces.knights, |sq, _| knight_attacks(sq), KNIGHT_MOBILITY);
+    add(&pieces.bishops, bishop_attacks, BISHOP_MOBILITY);
+    add(&pieces.rooks, rook_attacks, ROOK_MOBILITY);
+    add(&pieces.queens, queen_attacks, QUEEN_MOBILITY);
+    res
+}
@@ -1,36 +1,43 @@
 use chess_backend::{Colour, FinishedState, GameState, Pieces};
 
-use crate::engine::tree::Branch;
+use crate::engine::tree::Node;
 use crate::engine::utils::eval::Eval;
-use crate::engine::utils::phase::GamePhase;
+use crate::engine::utils::phase::{material_phase, taper_centipawns, GamePhase};
+pub mod king_safety;
+pub mod mobility;
+pub mod pawn_structure;
 mod piece_square_table;
+pub mod see;
 
-const MOBILITY_MOD: f32 = 0.1;
-const POSITIONAL_MOD: f32 = 0.01;
-// Piece values
-const PAWN_VAL: f32 = 1.;
-const KNIGHT_VAL: f32 = 3.;
-const BISHOP_VAL: f32 = 3.;
-const ROOK_VAL: f32 = 5.;
-const QUEEN_VAL: f32 = 9.;
+// Piece values in centipawns, in the middlegame and in the endgame
+const MG_PAWN_VAL: i32 = 100;
+const MG_KNIGHT_VAL: i32 = 300;
+const MG_BISHOP_VAL: i32 = 300;
+const MG_ROOK_VAL: i32 = 500;
+const MG_QUEEN_VAL: i32 = 900;
+const EG_PAWN_VAL: i32 = 120;
+const EG_KNIGHT_VAL: i32 = 280;
+const EG_BISHOP_VAL: i32 = 310;
+const EG_ROOK_VAL: i32 = 520;
+const EG_QUEEN_VAL: i32 = 930;
 
-impl Branch {
+impl Node {
     pub fn eval_position(&mut self, mobility: usize, depth: usize) -> Eval {
         match self.board.get_unchecked_game_state(mobility) {
-            GameState::Ongoing => self.eval_heuristic(mobility),
+            GameState::Ongoing => self.eval_heuristic(),
             GameState::Finished(state) => match state {
                 // With a finished state, the evaluation is absolute.
                 FinishedState::Win(c, _) => match c {
-                    Colour::White => Eval::Mate(depth, Colour::White),
-                    Colour::Black => Eval::Mate(depth, Colour::Black),
+                    Colour::White => Eval::mate(depth, Colour::White),
+                    Colour::Black => Eval::mate(depth, Colour::Black),
                 },
-                FinishedState::Draw(_) => Eval::Numeric(0.),
+                FinishedState::Draw(_) => Eval::DRAW,
             },
         }
     }
 
-    pub fn eval_heuristic(&mut self, mobility: usize) -> Eval {
-        let mut res = 0.;
+    pub fn eval_heuristic(&mut self) -> Eval {
+        let mut res = 0;
 
         let white_pieces = Pieces::from(self.board.base.white);
         let black_pieces = Pieces::from(self.board.base.black);
@@ -39,34 +46,73 @@ impl Branch {
         // Evaluation may depend on the game phase
         self.phase = Some(GamePhase::determine_phase(
             self.phase,
+            &self.board,
             &white_pieces,
             &black_pieces,
         ));
 
-        let piece_value_white = self.eval_pieces(&white_pieces);
-        let piece_value_black = self.eval_pieces(&black_pieces);
+        // Middlegame and endgame values are blended, so that an exchange only moves the
+        // evaluation a little towards the endgame
+        let material_phase = material_phase(&white_pieces, &black_pieces);
+
+        let piece_value_white = self.eval_pieces(&white_pieces, material_phase);
+        let piece_value_black = self.eval_pieces(&black_pieces, material_phase);
 
         res += piece_value_white - piece_value_black;
 
         // unwrapping here should be safe since phase has previously been determined
-        res += (piece_square_table::positional_evaluation(
+        res += piece_square_table::positional_evaluation(
             Colour::White,
             &white_pieces,
             self.phase.unwrap(),
+            material_phase,
         ) - piece_square_table::positional_evaluation(
             Colour::Black,
             &black_pieces,
             self.phase.unwrap(),
-        )) * POSITIONAL_MOD;
+            material_phase,
+        );
+
+        res += pawn_structure::evaluate(&white_pieces, &black_pieces, material_phase);
+        res += king_safety::evaluate(&white_pieces, &black_pieces, material_phase);
+
+        // Both sides' pieces are counted, rather than the legal moves of the side to move alone
+        res += mobility::evaluate(&white_pieces, &black_pieces, material_phase);
 
-        Eval::Numeric(res)
+        Eval::centipawns(res)
     }
 
-    fn eval_pieces(&self, pieces: &Pieces) -> f32 {
-        PAWN_VAL * pieces.pawns.len() as f32
-            + KNIGHT_VAL * pieces.knights.len() as f32
-            + BISHOP_VAL * pieces.bishops.len() as f32
-            + ROOK_VAL * pieces.rooks.len() as f32
-            + QUEEN_VAL * pieces.queens.len() as f32
+    fn eval_pieces(&self, pieces: &Pieces, material_phase: i32) -> i32 {
+        let counts = [
+            pieces.pawns.len(),
+            pieces.knights.len(),
+            pieces.bishops.len(),
+            pieces.rooks.len(),
+            pieces.queens.len(),
+        ];
+        let value = |values: [i32; 5]| {
+            values
+                .iter()
+                .zip(counts)
+                .map(|(value, count)| value * count as i32)
+                .sum::<i32>()
+        };
+        taper_centipawns(
+            value([
+                MG_PAWN_VAL,
+                MG_KNIGHT_VAL,
+                MG_BISHOP_VAL,
+                MG_ROOK_VAL,
+                MG_QUEEN_VAL,
+            ]),
+            value([
+                EG_PAWN_VAL,
+                EG_KNIGHT_VAL,
+                EG_BISHOP_VAL,
+                EG_ROOK_VAL,
+                EG_QUEEN_VAL,
+            ]),
+            material_phase,
+        )
     }
 }
@@ -0,0 +1,166 @@
+use std::cell::RefCell;
+
+use chess_backend::Pieces;
+
+use crate::engine::{
+    utils::{
+        bitboard::{adjacent_files, bitboard, file_mask, rank_mask, squares},
+        phase::taper_centipawns,
+    },
+    zobrist::{pawn_hash, piece_lists},
+};
+
+// Entries in the pawn hash table of every search thread
+const PAWN_TABLE_SIZE: usize = 1 << 14;
+
+// Values in centipawns, for the middlegame and the endgame
+const ISOLATED: (i32, i32) = (-15, -20);
+const DOUBLED: (i32, i32) = (-10, -25);
+const BACKWARD: (i32, i32) = (-10, -10);
+const CONNECTED: (i32, i32) = (7, 5);
+// For every island after the first
+const PAWN_ISLAND: (i32, i32) = (-5, -5);
+// By rank from the side's own end of the board
+const PASSED_MG: [i32; 8] = [0, 5, 10, 15, 25, 40, 60, 0];
+const PASSED_EG: [i32; 8] = [0, 10, 15, 25, 40, 65, 100, 0];
+// The passed pawn bonus is divided by this when the square in front of the pawn is occupied
+const BLOCKED_PASSER: i32 = 2;
+
+// Everything about the pawns that depends on nothing but the pawns, from white's point of view.
+// Passed pawns are kept as bitboards, as blockers can be any piece.
+#[derive(Debug, Clone, Copy, Default)]
+struct PawnEntry {
+    key: u64,
+    mg: i32,
+    eg: i32,
+    passed: [u64; 2],
+}
+
+thread_local! {
+    // Every search thread has a table of its own, so no locking is needed. A position without
+    // pawns has key 0 and evaluates to 0, just like an empty entry.
+    static PAWN_TABLE: RefCell<Vec<PawnEntry>> =
+        RefCell::new(vec![PawnEntry::default(); PAWN_TABLE_SIZE]);
+}
+
+/// Evaluation of the pawn structure in centipawns, from white's point of view. Doubled, isolated and
+/// backward pawns are penalised, as are pawn islands, while connected and passed pawns get a
+/// bonus. Passed pawns are worth more the further they are and the fewer pieces are left.
+pub fn evaluate(white_pieces: &Pieces, black_pieces: &Pieces, material_phase: i32) -> i32 {
+    let key = pawn_hash(&white_pieces.pawns, &black_pieces.pawns);
+    let entry = PAWN_TABLE.with(|table| {
+        let mut table = table.borrow_mut();
+        let slot = &mut table[key as usize % PAWN_TABLE_SIZE];
+        if slot.key != key {
+            *slot = analyse(key, &white_pieces.pawns, &black_pieces.pawns);
+        }
+        *slot
+    });
+
+    let occupied = [white_pieces, black_pieces]
+        .iter()
+        .flat_map(|pieces| piece_lists(pieces))
+        .fold(0, |occupied, squares| occupied | bitboard(squares));
+    let (white_mg, white_eg) = passed_bonus(entry.passed[0], 0, occupied);
+    let (black_mg, black_eg) = passed_bonus(entry.passed[1], 1, occupied);
+
+    taper_centipawns(
+        entry.mg + white_mg - black_mg,
+        entry.eg + white_eg - black_eg,
+        material_phase,
+    )
+}
+
+fn analyse(key: u64, white_pawns: &[i32], black_pawns: &[i32]) -> PawnEntry {
+    let pawns = [bitboard(white_pawns), bitboard(black_pawns)];
+    let mut entry = PawnEntry {
+        key,
+        ..PawnEntry::default()
+    };
+    for side in 0..2 {
+        let (mg, eg, passed) = side_structure(pawns[side], pawns[1 - side], side);
+        let sign = if side == 0 { 1 } else { -1 };
+        entry.mg += sign * mg;
+        entry.eg += sign * eg;
+        entry.passed[side] = passed;
+    }
+    entry
+}
+
+// The structure terms of one side, where side 0 is white, and its passed pawns
+fn side_structure(own: u64, enemy: u64, side: usize) -> (i32, i32, u64) {
+    // Ranks go up for white and down for black
+    let forward = |rank: i32, steps: i32| {
+        if side == 0 {
+            rank + steps
+        } else {
+            rank - steps
+        }
+    };
+    let mut terms = (0, 0);
+    let mut add = |(mg, eg): (i32, i32)| {
+        terms.0 += mg;
+        terms.1 += eg;
+    };
+    let mut passed = 0;
+
+    for sq in squares(own) {
+        let (file, rank) = (sq % 8, sq / 8);
+        let ahead = ahead_of(rank, side);
+        let neighbours = own & adjacent_files(file);
+
+        // Only the front pawn of a file can be passed, the ones behind it count as doubled
+        if own & file_mask(file) & ahead != 0 {
+            add(DOUBLED);
+        } else if enemy & (file_mask(file) | adjacent_files(file)) & ahead == 0 {
+            passed |= 1 << sq;
+        }
+
+        let connected = neighbours & (rank_mask(rank) | rank_mask(forward(rank, -1))) != 0;
+        if neighbours == 0 {
+            add(ISOLATED);
+        } else if connected {
+            add(CONNECTED);
+        } else if neighbours & !ahead == 0
+            && enemy & adjacent_files(file) & rank_mask(forward(rank, 2)) != 0
+        {
+            // Left behind by its neighbours, with its advance stopped by an enemy pawn
+            add(BACKWARD);
+        }
+    }
+
+    let islands = (0..8)
+        .filter(|file| {
+            own & file_mask(*file) != 0 && (*file == 0 || own & file_mask(file - 1) == 0)
+        })
+        .count();
+    for _ in 1..islands {
+        add(PAWN_ISLAND);
+    }
+    (terms.0, terms.1, passed)
+}
+
+fn passed_bonus(passed: u64, side: usize, occupied: u64) -> (i32, i32) {
+    squares(passed).fold((0, 0), |(mg, eg), sq| {
+        let rank = if side == 0 { sq / 8 } else { 7 - sq / 8 } as usize;
+        let stop = if side == 0 { sq + 8 } else { sq - 8 };
+        let divisor = if occupied & (1 << stop) != 0 {
+            BLOCKED_PASSER
+        } else {
+            1
+        };
+        (
+            mg + PASSED_MG[rank] / divisor,
+            eg + PASSED_EG[rank] / divisor,
+        )
+    })
+}
+
+// The ranks in front of `rank` from the point of view of `side`
+fn ahead_of(rank: i32, side: usize) -> u64 {
+    match (side, rank) {
+        (0, 7) | (1, 0) => 0,
+        (0, _) => !0 << (8 * (rank + 1)),
+        _ => (1 << (8 * rank)) - 1,
+    }
+}
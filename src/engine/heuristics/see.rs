@@ -0,0 +1,144 @@
+use chess_backend::{Board, Colour, Pieces};
+
+use crate::engine::{
+    utils::{
+        bitboard::{
+            bishop_attacks, bitboard, king_attacks, knight_attacks, pawn_attacks, rook_attacks,
+        },
+        lan::{LanMove, Promotion},
+    },
+    zobrist::{en_passant_square, piece_lists},
+};
+
+/// Piece values used by the exchange evaluation, in hundredths of a pawn, in the order pawn,
+/// knight, bishop, rook, queen, king.
+pub const SEE_VALUES: [i32; 6] = [100, 300, 300, 500, 900, 20_000];
+
+const PAWN: usize = 0;
+const KING: usize = 5;
+
+/// Static exchange evaluation of `mv` in `board`: the material the side to move wins, in
+/// hundredths of a pawn, if both sides keep capturing on the destination square with their least
+/// valuable piece for as long as it pays. Pieces lined up behind each other, x-rays, join in as
+/// the ones in front capture. Quiet moves give how much is lost by putting the piece where it can
+/// be taken.
+pub fn see(board: &Board, mv: LanMove) -> i32 {
+    let side = match board.side_to_move() {
+        Colour::White => 0,
+        Colour::Black => 1,
+    };
+    let pieces = [
+        pieces_by_kind(&Pieces::from(board.base.white)),
+        pieces_by_kind(&Pieces::from(board.base.black)),
+    ];
+    let target = 1 << mv.destination;
+    let Some(mut attacker) = kind_on(&pieces[side], mv.origin) else {
+        return 0;
+    };
+
+    let mut occupied = pieces
+        .iter()
+        .flatten()
+        .fold(0, |occupied, bb| occupied | bb);
+    let mut gains = Vec::with_capacity(32);
+    let captured = match kind_on(&pieces[1 - side], mv.destination) {
+        Some(kind) => SEE_VALUES[kind],
+        // The pawn taken en passant is not on the destination square
+        None if attacker == PAWN && en_passant_square(board) == Some(mv.destination) => {
+            let behind = if side == 0 {
+                mv.destination - 8
+            } else {
+                mv.destination + 8
+            };
+            occupied &= !(1 << behind);
+            SEE_VALUES[PAWN]
+        }
+        None => 0,
+    };
+    gains.push(captured);
+    if let Some(promotion) = mv.promotion {
+        attacker = promoted_kind(promotion);
+        gains[0] += SEE_VALUES[attacker] - SEE_VALUES[PAWN];
+    }
+    occupied &= !(1 << mv.origin);
+
+    let mut side = 1 - side;
+    loop {
+        let attackers = attackers_to(mv.destination, occupied, &pieces) & occupied & !target;
+        let Some((kind, sq)) = least_valuable(&pieces[side], attackers) else {
+            break;
+        };
+        // The king cannot capture into check
+        if kind == KING && attackers & !(1 << sq) & colour_mask(&pieces[1 - side]) != 0 {
+            break;
+        }
+
+        // Each capture wins the last piece to have captured, at the cost of what was won so far
+        let gain = SEE_VALUES[attacker] - gains[gains.len() - 1];
+        gains.push(gain);
+        // Neither side can do better by going on
+        if (-gains[gains.len() - 2]).max(gain) < 0 {
+            break;
+        }
+        attacker = kind;
+        occupied &= !(1 << sq);
+        side = 1 - side;
+    }
+
+    // Either side may stop capturing instead, whichever is better for it
+    while gains.len() > 1 {
+        let last = gains.pop().unwrap();
+        let previous = gains.last_mut().unwrap();
+        *previous = -(-*previous).max(last);
+    }
+    gains[0]
+}
+
+/// Whether `mv` wins at least `threshold` hundredths of a pawn in the exchange on its destination
+/// square, as used to prune captures that lose material.
+pub fn see_ge(board: &Board, mv: LanMove, threshold: i32) -> bool {
+    see(board, mv) >= threshold
+}
+
+fn pieces_by_kind(pieces: &Pieces) -> [u64; 6] {
+    piece_lists(pieces).map(bitboard)
+}
+
+fn colour_mask(pieces: &[u64; 6]) -> u64 {
+    pieces.iter().fold(0, |mask, bb| mask | bb)
+}
+
+fn kind_on(pieces: &[u64; 6], sq: i32) -> Option<usize> {
+    pieces.iter().position(|bb| bb & 1 << sq != 0)
+}
+
+fn least_valuable(pieces: &[u64; 6], attackers: u64) -> Option<(usize, i32)> {
+    pieces
+        .iter()
+        .enumerate()
+        .find(|(_, bb)| *bb & attackers != 0)
+        .map(|(kind, bb)| (kind, (bb & attackers).trailing_zeros() as i32))
+}
+
+// Pieces of both sides that attack `sq`, with sliders seen through the squares no longer occupied
+fn attackers_to(sq: i32, occupied: u64, pieces: &[[u64; 6]; 2]) -> u64 {
+    let [white, black] = pieces;
+    let diagonal = white[2] | white[4] | black[2] | black[4];
+    let straight = white[3] | white[4] | black[3] | black[4];
+    // A white pawn attacks `sq` from where a black pawn on `sq` would attack, and the other way
+    pawn_attacks(1 << sq, Colour::Black) & white[PAWN]
+        | pawn_attacks(1 << sq, Colour::White) & black[PAWN]
+        | knight_attacks(sq) & (white[1] | black[1])
+        | king_attacks(sq) & (white[KING] | black[KING])
+        | bishop_attacks(sq, occupied) & diagonal
+        | rook_attacks(sq, occupied) & straight
+}
+
+fn promoted_kind(promotion: Promotion) -> usize {
+    match promotion {
+        Promotion::Knight => 1,
+        Promotion::Bishop => 2,
+        Promotion::Rook => 3,
+        Promotion::Queen => 4,
+    }
+}
@@ -0,0 +1,95 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use chess_backend::Board;
+
+use crate::engine::quiescence::in_check;
+use crate::engine::utils::lan::LanMove;
+
+/// Outcome of a search for a forced mate.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MateResult {
+    /// The shortest forced mate: the moves of the side to move alternating with the replies
+    /// that hold out the longest, ending with the mating move.
+    Mate(Vec<LanMove>),
+    /// Every line was searched and none mates within the bound.
+    NoMate,
+    /// The search was stopped before it could tell.
+    Stopped,
+}
+impl MateResult {
+    /// The number of moves the side to move needs to mate, if a mate was found.
+    pub fn moves(&self) -> Option<usize> {
+        match self {
+            Self::Mate(line) => Some(line.len().div_ceil(2)),
+            Self::NoMate | Self::Stopped => None,
+        }
+    }
+}
+
+/// Looks for a forced mate by the side to move in at most `max_moves` of its moves. Mates are
+/// tried from the shortest up, every defence is searched, and only checks are tried for the
+/// mating move itself, so a `NoMate` result proves that there is no mate within the bound.
+pub fn find_mate(board: &Board, max_moves: usize, stop: &AtomicBool) -> MateResult {
+    for moves in 1..=max_moves {
+        if let Some(line) = attack(board, moves, stop) {
+            return MateResult::Mate(line);
+        }
+        if stop.load(Ordering::Relaxed) {
+            return MateResult::Stopped;
+        }
+    }
+    MateResult::NoMate
+}
+
+// A line that mates in at most `moves` moves of the side to move whatever the defence
+fn attack(board: &Board, moves: usize, stop: &AtomicBool) -> Option<Vec<LanMove>> {
+    if stop.load(Ordering::Relaxed) {
+        return None;
+    }
+
+    // Checks are the likeliest moves to force a mate, and the only ones that can give it
+    let mut children: Vec<(bool, Board)> = board
+        .generate_legal_moves()
+        .iter()
+        .map(|m| (in_check(&m.board), m.board))
+        .collect();
+    children.sort_by_key(|(check, _)| !check);
+
+    for (check, child) in children {
+        if moves == 1 && !check {
+            break;
+        }
+        if let Some(defence) = defend(&child, moves - 1, stop) {
+            // Unwrap is safe, the child was generated from the board
+            let mut line = vec![LanMove::from_boards(board, &child).unwrap()];
+            line.extend(defence);
+            return Some(line);
+        }
+    }
+    None
+}
+
+// The reply that holds out the longest against a mate in at most `moves` more moves, if none
+// escapes it. A position that is already checkmate needs no line at all.
+fn defend(board: &Board, moves: usize, stop: &AtomicBool) -> Option<Vec<LanMove>> {
+    let replies = board.generate_legal_moves();
+    if replies.is_empty() {
+        return in_check(board).then(Vec::new);
+    }
+
+    let mut longest: Option<Vec<LanMove>> = None;
+    for reply in replies.iter() {
+        // The shortest mate after this reply, as the attacker would play it
+        let mate = (1..=moves).find_map(|n| attack(&reply.board, n, stop))?;
+        // Unwrap is safe, the reply was generated from the board
+        let mut line = vec![LanMove::from_boards(board, &reply.board).unwrap()];
+        line.extend(mate);
+        if longest
+            .as_ref()
+            .is_none_or(|longest| line.len() > longest.len())
+        {
+            longest = Some(line);
+        }
+    }
+    longest
+}
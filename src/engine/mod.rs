@@ -1,207 +1,563 @@
 use std::{
-    slice::Iter,
-    sync::mpsc::{channel, Receiver, Sender},
+    iter,
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::{channel, Receiver, RecvTimeoutError, Sender},
+        Arc,
+    },
     time::{Duration, SystemTime},
 };
 
-use sqlite::{self, Connection};
-const DB_PATH: &str = "openings.db";
+/// Book looked for when no other is given, in the working directory or next to the executable.
+pub const DB_PATH: &str = "openings.db";
 
-use chess_backend::{Board, Colour, GameState};
+// Time kept in reserve for communicating with the GUI
+const MOVE_OVERHEAD: Duration = Duration::from_millis(50);
+const MIN_SEARCH_TIME: Duration = Duration::from_millis(10);
+// Assumed number of moves left in the game when the time control does not say
+const DEFAULT_MOVES_TO_GO: u32 = 30;
+// Iterative deepening stops here if nothing else ends the search first
+const MAX_DEPTH: usize = 64;
+// Memory shared by the search trees of all workers
+const TREE_MEMORY_LIMIT: usize = 256 * 1024 * 1024;
+
+use chess_backend::{Board, Colour, FinishedState, GameState};
+use mate_search::MateResult;
+use opening_book::{BookError, BookPolicy, OpeningBook};
+use pv::PrincipalVariation;
+use smp::ThreadReport;
 use threadpool::ThreadPool;
-use tree::Branch;
-use utils::{eval::Eval, phase::GamePhase};
+use transposition::TranspositionTable;
+use tree::Node;
+use utils::{
+    eval::Eval,
+    lan::LanMove,
+    phase::{GamePhase, OPENING_MAX_PLIES},
+};
 
 pub mod heuristics;
-mod opening_book;
+pub mod mate_search;
+pub mod move_ordering;
+pub mod opening_book;
+pub mod pv;
+pub mod quiescence;
+pub mod smp;
+pub mod transposition;
 pub mod tree;
 pub mod utils;
+pub mod zobrist;
+
+/// Bounds on a single search. A search without any limits runs until it is stopped.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SearchLimits {
+    pub time: Option<Duration>,
+    pub depth: Option<usize>,
+}
+impl SearchLimits {
+    pub fn time(time_limit: Duration) -> Self {
+        Self {
+            time: Some(time_limit),
+            ..Self::default()
+        }
+    }
 
-fn get_db_connection() -> Connection {
-    Connection::open(DB_PATH).expect("Failed to connect to opening database")
+    /// Budgets the time for one move out of the time left on the clock.
+    pub fn from_clock(time_left: Duration, increment: Duration, moves_to_go: Option<u32>) -> Self {
+        let budget =
+            time_left / moves_to_go.unwrap_or(DEFAULT_MOVES_TO_GO).max(1) + increment * 3 / 4;
+        Self::time(
+            budget
+                .min(time_left.saturating_sub(MOVE_OVERHEAD))
+                .max(MIN_SEARCH_TIME),
+        )
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct SearchResult {
+    pub board: Board,
+    pub best_move: Option<LanMove>,
+    pub eval: Option<Eval>,
+    pub depth: usize,
+    /// The line the engine expects, starting with the chosen move, and the search statistics.
+    pub pv: PrincipalVariation,
+}
+impl SearchResult {
+    fn from_node(node: &Node, depth: usize, pv: PrincipalVariation) -> Self {
+        Self {
+            board: node.board,
+            best_move: node.lan,
+            eval: node.eval,
+            depth,
+            pv,
+        }
+    }
 }
 
 pub struct EngineController {
     board: Board,
     n_workers: usize,
-    db_conn: Connection,
+    book: OpeningBook,
     phase: Option<GamePhase>,
+    history: Vec<(Board, Option<GamePhase>)>,
+    // Book moves played by the engine, with the positions before and after, while learning
+    book_moves: Option<Vec<(Board, Board, i64)>>,
+    stop: Arc<AtomicBool>,
+    // Kept between searches, so that later moves can reuse earlier work
+    tt: Arc<TranspositionTable>,
 }
 impl EngineController {
     pub fn init() {
         chess_backend::init();
     }
+    pub fn builder() -> EngineBuilder {
+        EngineBuilder::default()
+    }
+    /// Creates a controller with the book at `DB_PATH`, or without a book if there is none. Use
+    /// `EngineController::builder` to choose the book.
     pub fn new(board: Board, n_workers: usize) -> Self {
+        Self::with_book(board, n_workers, OpeningBook::find(DB_PATH))
+    }
+    fn with_book(board: Board, n_workers: usize, book: OpeningBook) -> Self {
         Self {
             board,
             n_workers,
-            db_conn: get_db_connection(),
+            book,
             phase: None,
+            history: Vec::new(),
+            book_moves: None,
+            stop: Arc::new(AtomicBool::new(false)),
+            tt: Arc::new(TranspositionTable::default()),
+        }
+    }
+    /// Searches the current position without playing the chosen move. `on_iteration` is called
+    /// with every result that is deeper than the ones before, as soon as it is known, its node
+    /// count that of all threads together. Moves from the book are not reported. The stop flag is
+    /// left as it is, so it has to be cleared before the search is started.
+    pub fn search(
+        &mut self,
+        limits: SearchLimits,
+        on_iteration: impl FnMut(&SearchResult),
+    ) -> SearchResult {
+        let mut engine = Engine::new(
+            self.board,
+            self.n_workers,
+            self.phase,
+            self.tt.clone(),
+            self.stop.clone(),
+        );
+        let res = engine.begin_search(limits, self.phase, &mut self.book, on_iteration);
+        self.record_book_move(&res.board);
+        res
+    }
+
+    /// Looks for a forced mate by the side to move in at most `max_moves` moves and returns the
+    /// mating line. Unlike a normal search it either finds the shortest mate or proves that there
+    /// is none within the bound, unless it is stopped first. The book is not consulted.
+    /// Like `search`, it leaves the stop flag as it is.
+    pub fn find_mate(&mut self, max_moves: usize) -> MateResult {
+        mate_search::find_mate(&self.board, max_moves, &self.stop)
+    }
+
+    /// Flag that ends a running search as soon as it is set. The search still reports the best
+    /// move found so far.
+    pub fn stop_flag(&self) -> Arc<AtomicBool> {
+        self.stop.clone()
+    }
+
+    /// Chooses how moves are picked from the opening book.
+    pub fn set_book_policy(&mut self, policy: BookPolicy) {
+        self.book.set_policy(policy);
+    }
+
+    /// Replaces the opening book. The policy and seed of the old book are not kept.
+    pub fn set_book(&mut self, book: OpeningBook) {
+        self.book = book;
+    }
+
+    /// Looks positions up in the opening book by position rather than by the moves played, see
+    /// `OpeningBook::set_position_lookup`.
+    pub fn set_book_position_lookup(&mut self, enabled: bool) -> Result<(), sqlite::Error> {
+        self.book.set_position_lookup(enabled)
+    }
+
+    /// Makes the engine remember the book moves it plays and add the result of the game to them
+    /// once it is over, so that lines that lost are played less. Only sqlite books learn.
+    pub fn set_book_learning(&mut self, enabled: bool) -> Result<(), sqlite::Error> {
+        self.book_moves = if enabled {
+            self.book.enable_learning()?;
+            Some(self.book_moves.take().unwrap_or_default())
+        } else {
+            None
+        };
+        Ok(())
+    }
+
+    /// Seeds the random choices of the opening book, so that games can be reproduced.
+    pub fn seed_book(&mut self, seed: u64) {
+        self.book.seed(seed);
+    }
+
+    /// Forgets everything learned in earlier searches, as when a new game starts.
+    pub fn clear_hash(&self) {
+        self.tt.clear();
+    }
+
+    /// Sets up the starting position for a new game. Book moves from an unfinished game are not
+    /// learned from.
+    pub fn new_game(&mut self) {
+        self.clear_hash();
+        if let Some(book_moves) = &mut self.book_moves {
+            book_moves.clear();
+        }
+        self.set_start_position();
+    }
+
+    /// Replaces the current position. Unless the opening book knows the position, the game phase
+    /// is determined again at the next search.
+    pub fn set_position(&mut self, board: Board) {
+        self.board = board;
+        self.phase = self.book.probe(&board);
+        self.history.clear();
+    }
+
+    /// Sets up the starting position, from which the opening book is followed.
+    pub fn set_start_position(&mut self) {
+        self.set_position(Board::default());
+        let book_entry = (!self.book.is_empty()).then_some(opening_book::BOOK_ROOT);
+        self.phase = Some(GamePhase::Opening(book_entry));
+    }
+
+    /// Plays `mv` if it is legal in the current position.
+    pub fn make_move(&mut self, mv: LanMove) -> bool {
+        if let Some(board) = mv.apply(&self.board) {
+            self.history.push((self.board, self.phase));
+            if let Some(GamePhase::Opening(Some(id))) = self.phase {
+                self.phase = Some(self.book.follow_move(&self.board, id, &board));
+            }
+            self.board = board;
+            self.end_long_opening();
+            self.learn(&board);
+            true
+        } else {
+            false
+        }
+    }
+
+    // The board does not count moves, so the opening is ended here once it has gone on long enough
+    fn end_long_opening(&mut self) {
+        if let (Some(GamePhase::Opening(None)), true) =
+            (self.phase, self.history.len() >= OPENING_MAX_PLIES)
+        {
+            self.phase = Some(GamePhase::MiddleGame);
+        }
+    }
+
+    // Remembers the move from the current position to `next` if it is in the book, then learns
+    // from the game if the move ends it
+    fn record_book_move(&mut self, next: &Board) {
+        if let (Some(book_moves), Some(GamePhase::Opening(Some(id)))) =
+            (&mut self.book_moves, self.phase)
+        {
+            match self.book.entry_id(&self.board, id, next) {
+                // Searching the same position again does not play the move twice
+                Some(entry) if !book_moves.iter().any(|(_, _, e)| *e == entry) => {
+                    book_moves.push((self.board, *next, entry))
+                }
+                _ => (),
+            }
         }
+        self.learn(next);
     }
-    pub fn pick_move(&mut self, time_limit: Duration) {
-        let mut engine = Engine::new(self.board, self.n_workers, self.phase);
-        (self.board, self.phase) = engine.begin_search(time_limit, self.phase, &self.db_conn);
+
+    /// Adds the result of a game that ended in the current position by other means than the
+    /// rules, such as a resignation or an adjudication, to the book moves played in it. `winner`
+    /// is None for a draw. Only matters while the book is learning.
+    pub fn game_over(&mut self, winner: Option<Colour>) {
+        self.learn_result(None, winner);
+    }
+
+    // Once the game has ended in `last`, adds the result to the recorded book moves that were
+    // played in it
+    fn learn(&mut self, last: &Board) {
+        if self.book_moves.as_ref().is_none_or(Vec::is_empty) {
+            return;
+        }
+        let winner = match last.get_game_state() {
+            GameState::Finished(FinishedState::Win(winner, _)) => Some(winner),
+            GameState::Finished(FinishedState::Draw(_)) => None,
+            GameState::Ongoing => return,
+        };
+        self.learn_result(Some(last), winner);
     }
 
-    pub fn show_board(&self) {
-        println!("{}", self.board);
+    // Adds the result to the recorded book moves of the game that led to the current position,
+    // and on to `last` if given. Positions may have been set up again since a move was recorded,
+    // as with UCI, so only moves found between consecutive positions of the game count.
+    fn learn_result(&mut self, last: Option<&Board>, winner: Option<Colour>) {
+        let Some(book_moves) = &mut self.book_moves else {
+            return;
+        };
+        if book_moves.is_empty() {
+            return;
+        }
+
+        let game: Vec<u64> = self
+            .history
+            .iter()
+            .map(|(board, _)| board)
+            .chain(iter::once(&self.board))
+            .chain(last)
+            .map(zobrist::hash)
+            .collect();
+        let played: Vec<(i64, Colour)> = book_moves
+            .drain(..)
+            .filter(|(before, after, _)| {
+                let mv = [zobrist::hash(before), zobrist::hash(after)];
+                game.windows(2).any(|w| w == mv)
+            })
+            .map(|(before, _, id)| (id, before.side_to_move()))
+            .collect();
+        // A book that cannot be written to just does not learn
+        let _ = self.book.learn(&played, winner);
     }
 
-    pub fn get_game_state(&self) -> GameState {
-        self.board.get_game_state()
+    /// Takes back the last move played. Returns false if there is nothing to take back.
+    pub fn undo(&mut self) -> bool {
+        if let Some((board, phase)) = self.history.pop() {
+            (self.board, self.phase) = (board, phase);
+            true
+        } else {
+            false
+        }
     }
 
-    pub fn is_over(&self) -> bool {
-        self.get_game_state() != GameState::Ongoing
+    pub fn board(&self) -> &Board {
+        &self.board
     }
 }
 impl Default for EngineController {
+    fn default() -> Self {
+        let mut controller = Self::new(Board::default(), num_cpus::get());
+        controller.phase = Some(GamePhase::Opening(Some(opening_book::BOOK_ROOT)));
+        controller
+    }
+}
+
+// Which book an `EngineBuilder` opens
+#[derive(Debug, Clone)]
+enum BookChoice {
+    Default,
+    Path(PathBuf),
+    None,
+}
+
+/// Configures an `EngineController`, which starts from the starting position. Unless told
+/// otherwise it uses every core and looks for the book at `DB_PATH`.
+#[derive(Debug, Clone)]
+pub struct EngineBuilder {
+    n_workers: usize,
+    book: BookChoice,
+}
+impl EngineBuilder {
+    pub fn workers(mut self, n_workers: usize) -> Self {
+        self.n_workers = n_workers.max(1);
+        self
+    }
+
+    /// Plays from the book at `path`, see `OpeningBook::open`.
+    pub fn book(mut self, path: impl Into<PathBuf>) -> Self {
+        self.book = BookChoice::Path(path.into());
+        self
+    }
+
+    /// Searches every position, even in the opening.
+    pub fn without_book(mut self) -> Self {
+        self.book = BookChoice::None;
+        self
+    }
+
+    /// Creates the controller. Only a book that was asked for by path can fail to open, without
+    /// one the engine plays without a book.
+    pub fn build(self) -> Result<EngineController, BookError> {
+        let book = match self.book {
+            BookChoice::Default => OpeningBook::find(DB_PATH),
+            BookChoice::Path(path) => OpeningBook::open(path)?,
+            BookChoice::None => OpeningBook::empty(),
+        };
+        let mut controller = EngineController::with_book(Board::default(), self.n_workers, book);
+        controller.set_start_position();
+        Ok(controller)
+    }
+}
+impl Default for EngineBuilder {
     fn default() -> Self {
         Self {
-            board: Board::default(),
             n_workers: num_cpus::get(),
-            db_conn: get_db_connection(),
-            phase: Some(GamePhase::Opening(1)),
+            book: BookChoice::Default,
         }
     }
 }
 
 #[derive(Debug)]
 struct Engine {
-    branch: Branch,
+    root: Node,
+    n_workers: usize,
     workers: ThreadPool,
-    sender_model: Sender<(Branch, Vec<usize>, [Option<(Eval, Vec<usize>)>; 3], bool)>,
-    receiver: Receiver<(Branch, Vec<usize>, [Option<(Eval, Vec<usize>)>; 3], bool)>,
+    sender_model: Sender<ThreadReport>,
+    receiver: Receiver<ThreadReport>,
+    tt: Arc<TranspositionTable>,
+    stop: Arc<AtomicBool>,
 }
 impl Engine {
-    pub fn new(board: Board, n_workers: usize, phase: Option<GamePhase>) -> Self {
+    pub fn new(
+        board: Board,
+        n_workers: usize,
+        phase: Option<GamePhase>,
+        tt: Arc<TranspositionTable>,
+        stop: Arc<AtomicBool>,
+    ) -> Self {
         let (sender_model, receiver) = channel();
         Self {
-            branch: Branch::from_parent(board, phase),
+            root: Node::from_parent(board, phase),
+            n_workers,
             workers: ThreadPool::new(n_workers),
             sender_model,
             receiver,
+            tt,
+            stop,
         }
     }
     pub fn begin_search(
         &mut self,
-        time_limit: Duration,
+        limits: SearchLimits,
         phase: Option<GamePhase>,
-        db_conn: &Connection,
-    ) -> (Board, Option<GamePhase>) {
+        book: &mut OpeningBook,
+        on_iteration: impl FnMut(&SearchResult),
+    ) -> SearchResult {
         if let Some(p) = phase {
             match p {
-                GamePhase::Opening(id) => opening_book::find_bookmove(db_conn, id),
-                _ => self.search(time_limit),
+                GamePhase::Opening(Some(id)) => {
+                    match book.find_bookmove(&self.root.board, id) {
+                        // The controller follows the book itself once the move is played
+                        Some((board, _)) => SearchResult {
+                            board,
+                            best_move: LanMove::from_boards(&self.root.board, &board),
+                            eval: None,
+                            depth: 0,
+                            pv: PrincipalVariation::from_line(&self.root.board, &[board]),
+                        },
+                        None => {
+                            // Out of book, though not necessarily out of the opening
+                            self.root.phase = Some(GamePhase::Opening(None));
+                            self.search(limits, on_iteration)
+                        }
+                    }
+                }
+                _ => self.search(limits, on_iteration),
             }
         } else {
             // Likely the first search, meaning the phase has yet to be determined
-            self.search(time_limit)
+            self.search(limits, on_iteration)
         }
     }
-    fn search(&mut self, time_limit: Duration) -> (Board, Option<GamePhase>) {
-        let criteria = if self.branch.board.side_to_move() == Colour::White {
-            Eval::NegInfinity
-        } else {
-            Eval::Infinity
-        };
-        self.add_job([Some(vec![]), None, None].iter(), criteria);
-        let start_time = SystemTime::now();
-        while start_time.elapsed().unwrap() < time_limit {
-            for (res_branch, location, res, continue_search) in self.receiver.try_iter() {
-                println!("Finished node");
-                self.branch.insert_branch(res_branch, location.as_slice());
-
-                if continue_search {
-                    if let Some(criteria) = res[0].clone() {
-                        self.add_job(
-                            [
-                                Some(res[0].clone().unwrap().1),
-                                if let Some(e) = res[1].clone() {
-                                    Some(e.1)
-                                } else {
-                                    None
-                                },
-                                if let Some(e) = res[2].clone() {
-                                    Some(e.1)
-                                } else {
-                                    None
-                                },
-                            ]
-                            .iter(),
-                            criteria.0,
-                        );
-                    }
-                } else {
-                    println!("Node at {location:?} failed to meet required criteria. Terminating search.");
+
+    // Lazy SMP. Every worker runs its own iterative deepening from the root and they share the
+    // transposition table, which is what makes the helpers useful to each other. Results only
+    // come from completed iterations, and the deepest one reported by any worker is played.
+    fn search(
+        &mut self,
+        limits: SearchLimits,
+        mut on_iteration: impl FnMut(&SearchResult),
+    ) -> SearchResult {
+        let start = SystemTime::now();
+        let deadline = limits.time.map(|time_limit| start + time_limit);
+        let max_depth = limits.depth.unwrap_or(MAX_DEPTH);
+
+        // Until the first iteration completes, any legal move will have to do
+        let mut best = match self.root.generate_children().first() {
+            Some(child) => SearchResult::from_node(
+                child,
+                0,
+                PrincipalVariation::from_line(&self.root.board, &[child.board]),
+            ),
+            None => {
+                return SearchResult {
+                    best_move: None,
+                    ..SearchResult::from_node(&self.root, 0, PrincipalVariation::default())
                 }
             }
-        }
-        // When an answer has been demanded, let the current threads finish
-        println!("Joining workers");
-        self.workers.join();
-        // Then choose the best branch from the explored tree
-        let best = self
-            .branch
-            .get_best(self.branch.board.side_to_move() == Colour::White);
+        };
+        // The latest count reported by every worker
+        let mut nodes = vec![0; self.n_workers];
 
-        if let Some(chosen) = best {
-            (chosen.board, chosen.phase)
-        } else {
-            println!("Incomplete");
-            self.search(time_limit * 2)
+        let memory_limit = TREE_MEMORY_LIMIT / self.n_workers;
+        for thread_id in 0..self.n_workers {
+            let root = self.root.clone();
+            let tx = self.sender_model.clone();
+            let tt = self.tt.clone();
+            let stop = self.stop.clone();
+            self.workers.execute(move || {
+                smp::search_thread(thread_id, root, max_depth, memory_limit, &tt, &stop, &tx);
+            });
         }
-    }
-    fn add_job(&self, mut locations: Iter<Option<Vec<usize>>>, criteria: Eval) {
-        self.handle_primary(
-            locations
-                .next()
-                .unwrap()
-                .clone()
-                .expect("Primary branch has no moves"),
-        );
-        for _ in 0..2 {
-            if let Some(location) = locations.next().unwrap().clone() {
-                self.handle_secondary(location, criteria);
+
+        let mut running = self.n_workers;
+        while running > 0 {
+            let res = match deadline {
+                Some(deadline) if !self.stop.load(Ordering::Relaxed) => self.receiver.recv_timeout(
+                    deadline
+                        .duration_since(SystemTime::now())
+                        .unwrap_or(Duration::ZERO),
+                ),
+                _ => self
+                    .receiver
+                    .recv()
+                    .map_err(|_| RecvTimeoutError::Disconnected),
+            };
+            match res {
+                Ok((thread_id, Some(res), thread_nodes)) => {
+                    nodes[thread_id] = thread_nodes;
+                    // The other workers have nothing left to add once the depth limit is reached
+                    if res.depth == max_depth {
+                        self.stop.store(true, Ordering::Relaxed);
+                    }
+                    if res.depth > best.depth {
+                        best = res;
+                        best.pv.nodes = nodes.iter().sum();
+                        best.pv.time = start.elapsed().unwrap_or_default();
+                        on_iteration(&best);
+                    }
+                }
+                Ok((thread_id, None, thread_nodes)) => {
+                    nodes[thread_id] = thread_nodes;
+                    running -= 1;
+                }
+                // Stopped workers still report back, so keep waiting for them
+                Err(RecvTimeoutError::Timeout) => self.stop.store(true, Ordering::Relaxed),
+                Err(RecvTimeoutError::Disconnected) => panic!("Search workers disconnected"),
             }
         }
-    }
-
-    fn handle_primary(&self, location: Vec<usize>) {
-        let tx = self.sender_model.clone();
-        let mut node = self.branch.find_branch(&location.as_slice()).clone();
-        self.workers.execute(move || {
-            let maximize = node.board.side_to_move() == Colour::White;
-            let res = node.run_node(3, location.as_slice(), maximize);
-            tx.send((node, location.clone(), res, true))
-                .expect("Failed to send finished branch");
-        });
-    }
-    // A secondary node must be rated higher than its primary after one cycle or we discontinue the
-    // search
-    fn handle_secondary(&self, location: Vec<usize>, criteria: Eval) {
-        let tx = self.sender_model.clone();
-        let mut node = self.branch.find_branch(&location.as_slice()).clone();
-        self.workers.execute(move || {
-            let maximize = node.board.side_to_move() == Colour::White;
-            let next_location = node.run_node(3, location.as_slice(), maximize);
-            let updated_value = node.simple_minimax(maximize);
-            let continue_search =
-                (maximize && updated_value > criteria) || (!maximize && updated_value < criteria);
-            tx.send((node, location.clone(), next_location, continue_search))
-                .expect("Failed to send finished branch");
-        });
+        best.pv.nodes = nodes.iter().sum();
+        best.pv.time = start.elapsed().unwrap_or_default();
+        best
     }
 }
 impl Default for Engine {
     fn default() -> Self {
         let (sender_model, receiver) = channel();
         Self {
-            branch: Branch::from_parent(Board::default(), Some(GamePhase::Opening(1))),
+            root: Node::from_parent(
+                Board::default(),
+                Some(GamePhase::Opening(Some(opening_book::BOOK_ROOT))),
+            ),
+            n_workers: num_cpus::get(),
             workers: ThreadPool::default(),
             sender_model,
             receiver,
+            tt: Arc::new(TranspositionTable::default()),
+            stop: Arc::new(AtomicBool::new(false)),
         }
     }
 }
@@ -0,0 +1,195 @@
+use std::{cell::RefCell, cmp::Reverse};
+
+use chess_backend::{Board, Colour, Pieces};
+
+use crate::engine::heuristics::see::see;
+use crate::engine::tree::Node;
+use crate::engine::utils::lan::{LanMove, Promotion};
+
+// Score bands, from the first moves to be searched to the last
+const HASH_MOVE_SCORE: i32 = 1_000_000;
+const CAPTURE_SCORE: i32 = 100_000;
+const PROMOTION_SCORE: i32 = 90_000;
+const KILLER_SCORES: [i32; 2] = [80_000, 79_000];
+// History scores are kept below the killers
+const HISTORY_MAX: u32 = 70_000;
+// Captures that lose material in the exchange go after every quiet move
+const LOSING_CAPTURE_SCORE: i32 = -100_000;
+
+// Piece values used for MVV-LVA, in the order pawn, knight, bishop, rook, queen, king
+const ORDERING_VALUES: [i32; 6] = [1, 3, 3, 5, 9, 100];
+
+const MAX_PLY: usize = 128;
+
+thread_local! {
+    /// Every search thread keeps its own tables, so they need no synchronisation.
+    pub static ORDERING_TABLES: RefCell<OrderingTables> = RefCell::new(OrderingTables::default());
+}
+
+/// Killer moves per ply and a history of quiet moves that caused cutoffs, used to order the
+/// moves that are neither hash moves nor captures.
+#[derive(Debug, Clone)]
+pub struct OrderingTables {
+    killers: Vec<[Option<LanMove>; 2]>,
+    history: Box<[[u32; 64]; 64]>,
+}
+impl OrderingTables {
+    /// Sorts the children of `parent` so that the most promising moves are searched first:
+    /// the hash move, captures by MVV-LVA, promotions, killer moves, history and then captures
+    /// that lose material.
+    pub fn order(
+        &self,
+        parent: &Board,
+        children: &mut [Node],
+        hash_move: Option<LanMove>,
+        ply: usize,
+    ) {
+        let scorer = MoveScorer::new(parent);
+        let killers = self.killers.get(ply).copied().unwrap_or_default();
+        children.sort_by_cached_key(|child| {
+            let Some(mv) = child.lan else {
+                return Reverse(0);
+            };
+            let score = if Some(mv) == hash_move {
+                HASH_MOVE_SCORE
+            } else if let Some(score) = scorer.tactical_score(mv, &child.board) {
+                score
+            } else if let Some(i) = killers.iter().position(|k| *k == Some(mv)) {
+                KILLER_SCORES[i]
+            } else {
+                self.history[mv.origin as usize][mv.destination as usize] as i32
+            };
+            Reverse(score)
+        });
+    }
+
+    /// Remembers a quiet move that caused a cutoff.
+    pub fn record_cutoff(&mut self, mv: LanMove, ply: usize, remaining_depth: usize) {
+        if let Some(killers) = self.killers.get_mut(ply) {
+            if killers[0] != Some(mv) {
+                killers[1] = killers[0];
+                killers[0] = Some(mv);
+            }
+        }
+
+        let entry = &mut self.history[mv.origin as usize][mv.destination as usize];
+        *entry += (remaining_depth * remaining_depth) as u32;
+        if *entry > HISTORY_MAX {
+            // Age the whole table so that the relative order is kept
+            for row in self.history.iter_mut() {
+                for score in row.iter_mut() {
+                    *score /= 2;
+                }
+            }
+        }
+    }
+}
+impl Default for OrderingTables {
+    fn default() -> Self {
+        Self {
+            killers: vec![[None; 2]; MAX_PLY],
+            history: Box::new([[0; 64]; 64]),
+        }
+    }
+}
+
+/// Sorts captures and promotions in a quiescence node, most valuable victim first and losing
+/// captures last.
+pub fn order_tactical(parent: &Board, children: &mut [Node]) {
+    let scorer = MoveScorer::new(parent);
+    children.sort_by_cached_key(|child| {
+        Reverse(
+            child
+                .lan
+                .and_then(|mv| scorer.tactical_score(mv, &child.board))
+                .unwrap_or(0),
+        )
+    });
+}
+
+struct MoveScorer {
+    board: Board,
+    mover: Pieces,
+    opponent: Pieces,
+    opponent_colour: Colour,
+}
+impl MoveScorer {
+    fn new(board: &Board) -> Self {
+        let (mover, opponent, opponent_colour) = match board.side_to_move() {
+            Colour::White => (
+                Pieces::from(board.base.white),
+                Pieces::from(board.base.black),
+                Colour::Black,
+            ),
+            Colour::Black => (
+                Pieces::from(board.base.black),
+                Pieces::from(board.base.white),
+                Colour::White,
+            ),
+        };
+        Self {
+            board: *board,
+            mover,
+            opponent,
+            opponent_colour,
+        }
+    }
+
+    // MVV-LVA for captures and the promoted piece for promotions, None for quiet moves. Captures
+    // by a more valuable piece than the victim are checked with SEE and go last if they lose.
+    fn tactical_score(&self, mv: LanMove, child: &Board) -> Option<i32> {
+        let opponent_after = match self.opponent_colour {
+            Colour::White => Pieces::from(child.base.white),
+            Colour::Black => Pieces::from(child.base.black),
+        };
+        let victim = kind_counts(&self.opponent)
+            .iter()
+            .zip(kind_counts(&opponent_after))
+            .position(|(before, after)| *before > after);
+
+        match (victim, mv.promotion) {
+            (Some(victim), _) => {
+                let attacker = kind_at(&self.mover, mv.origin).unwrap_or(0);
+                let mvv_lva = ORDERING_VALUES[victim] * 10 - ORDERING_VALUES[attacker];
+                if ORDERING_VALUES[attacker] > ORDERING_VALUES[victim] && see(&self.board, mv) < 0 {
+                    Some(LOSING_CAPTURE_SCORE + mvv_lva)
+                } else {
+                    Some(CAPTURE_SCORE + mvv_lva)
+                }
+            }
+            (None, Some(promotion)) => Some(
+                PROMOTION_SCORE
+                    + match promotion {
+                        Promotion::Queen => ORDERING_VALUES[4],
+                        Promotion::Rook => ORDERING_VALUES[3],
+                        Promotion::Bishop => ORDERING_VALUES[2],
+                        Promotion::Knight => ORDERING_VALUES[1],
+                    },
+            ),
+            (None, None) => None,
+        }
+    }
+}
+
+fn kind_counts(pieces: &Pieces) -> [usize; 5] {
+    [
+        pieces.pawns.len(),
+        pieces.knights.len(),
+        pieces.bishops.len(),
+        pieces.rooks.len(),
+        pieces.queens.len(),
+    ]
+}
+
+fn kind_at(pieces: &Pieces, sq: i32) -> Option<usize> {
+    [
+        &pieces.pawns,
+        &pieces.knights,
+        &pieces.bishops,
+        &pieces.rooks,
+        &pieces.queens,
+        &pieces.king,
+    ]
+    .iter()
+    .position(|squares| squares.contains(&sq))
+}
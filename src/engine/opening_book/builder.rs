@@ -0,0 +1,370 @@
+use std::{collections::HashMap, iter, mem};
+
+use chess_backend::{Board, Colour};
+use sqlite::{Connection, State};
+
+use super::polyglot::{encode_move, polyglot_key, PolyglotBook, PolyglotEntry};
+use super::{board_after, same_san, BOOK_ROOT, MIN_FREQUENCY};
+use crate::engine::utils::lan::LanMove;
+
+/// Plies of every game that are added to the book unless configured otherwise.
+pub const DEFAULT_MAX_PLIES: usize = 20;
+/// Games a move needs before it is written to the book, unless configured otherwise.
+pub const DEFAULT_MIN_GAMES: i64 = MIN_FREQUENCY;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GameResult {
+    WhiteWins,
+    Draw,
+    BlackWins,
+    Unknown,
+}
+impl GameResult {
+    fn parse(result: &str) -> Option<Self> {
+        match result {
+            "1-0" => Some(Self::WhiteWins),
+            "1/2-1/2" => Some(Self::Draw),
+            "0-1" => Some(Self::BlackWins),
+            "*" => Some(Self::Unknown),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct BuildNode {
+    board: Board,
+    san: String,
+    children: Vec<usize>,
+    games: i64,
+    // From the point of view of the side that played the move
+    wins: i64,
+    draws: i64,
+    losses: i64,
+}
+impl BuildNode {
+    fn new(board: Board, san: String) -> Self {
+        Self {
+            board,
+            san,
+            children: Vec::new(),
+            games: 0,
+            wins: 0,
+            draws: 0,
+            losses: 0,
+        }
+    }
+
+    // Evaluation in pawns from white's point of view, estimated from the results of the games.
+    // A score of p corresponds to 4 * log10(p / (1 - p)) pawns, the usual relation between
+    // centipawns and Elo. One win and one loss are added so that few games give a small value.
+    fn eval(&self, mover: Colour) -> f64 {
+        let score = (self.wins as f64 + self.draws as f64 / 2. + 1.)
+            / (self.wins + self.draws + self.losses + 2) as f64;
+        let eval = 4. * (score / (1. - score)).log10();
+        match mover {
+            Colour::White => eval,
+            Colour::Black => -eval,
+        }
+    }
+}
+
+/// Aggregates games into a tree of book moves, which can then be written as an sqlite book or a
+/// Polyglot book.
+#[derive(Debug, Clone)]
+pub struct BookBuilder {
+    max_plies: usize,
+    min_games: i64,
+    // The root, at index 0, is the starting position
+    nodes: Vec<BuildNode>,
+}
+impl BookBuilder {
+    pub fn new(max_plies: usize, min_games: i64) -> Self {
+        Self {
+            max_plies,
+            min_games,
+            nodes: vec![BuildNode::new(Board::default(), String::new())],
+        }
+    }
+
+    /// Adds the moves of an existing sqlite book, so that writing the book extends it instead of
+    /// replacing it.
+    pub fn load_sqlite(&mut self, db_conn: &Connection) -> Result<(), sqlite::Error> {
+        let mut stm = db_conn.prepare("SELECT * FROM moves")?;
+        let mut children: HashMap<i64, Vec<(i64, String, [i64; 4])>> = HashMap::new();
+        while let State::Row = stm.next()? {
+            // Books written before results were recorded only have the frequency
+            let counts = [
+                stm.read::<i64, _>("frequency")?,
+                stm.read::<i64, _>("wins").unwrap_or(0),
+                stm.read::<i64, _>("draws").unwrap_or(0),
+                stm.read::<i64, _>("losses").unwrap_or(0),
+            ];
+            let parent_move = stm.read::<i64, _>("parent_move")?;
+            // The starting position, which counts all games
+            if parent_move == 0 {
+                self.nodes[0].games += counts[0];
+                continue;
+            }
+            children.entry(parent_move).or_default().push((
+                stm.read("id")?,
+                stm.read("san")?,
+                counts,
+            ));
+        }
+
+        let mut stack = vec![(BOOK_ROOT, 0)];
+        while let Some((id, node)) = stack.pop() {
+            for (child_id, san, [games, wins, draws, losses]) in
+                children.remove(&id).into_iter().flatten()
+            {
+                if let Some(child) = self.child(node, &san) {
+                    let child_node = &mut self.nodes[child];
+                    child_node.games += games;
+                    child_node.wins += wins;
+                    child_node.draws += draws;
+                    child_node.losses += losses;
+                    stack.push((child_id, child));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Adds every game of a PGN text. Returns the number of games added.
+    pub fn add_pgn(&mut self, pgn: &str) -> usize {
+        let games = parse_pgn(pgn);
+        for (moves, result) in &games {
+            self.add_game(moves, *result);
+        }
+        games.len()
+    }
+
+    /// Adds a single game given by the SAN of its moves. Moves from the first one that is not
+    /// legal on are ignored.
+    pub fn add_game(&mut self, moves: &[String], result: GameResult) {
+        let mut node = 0;
+        self.nodes[0].games += 1;
+        for san in moves.iter().take(self.max_plies) {
+            let mover = self.nodes[node].board.side_to_move();
+            let Some(child) = self.child(node, san) else {
+                break;
+            };
+
+            let child_node = &mut self.nodes[child];
+            child_node.games += 1;
+            match (result, mover) {
+                (GameResult::Unknown, _) => (),
+                (GameResult::Draw, _) => child_node.draws += 1,
+                (GameResult::WhiteWins, Colour::White) | (GameResult::BlackWins, Colour::Black) => {
+                    child_node.wins += 1
+                }
+                _ => child_node.losses += 1,
+            }
+            node = child;
+        }
+    }
+
+    // Finds or creates the child of `node` reached by `san`
+    fn child(&mut self, node: usize, san: &str) -> Option<usize> {
+        if let Some(child) = self.nodes[node]
+            .children
+            .iter()
+            .find(|c| same_san(&self.nodes[**c].san, san))
+        {
+            return Some(*child);
+        }
+
+        // Stored the way the engine writes it, so that lookups match exactly
+        let board = self.nodes[node].board;
+        let next = board_after(&board, san)?;
+        self.nodes.push(BuildNode::new(next, board.get_san(&next)));
+        let child = self.nodes.len() - 1;
+        self.nodes[node].children.push(child);
+        Some(child)
+    }
+
+    // Children that were played often enough to be written
+    fn kept_children(&self, node: usize) -> impl Iterator<Item = usize> + '_ {
+        self.nodes[node]
+            .children
+            .iter()
+            .copied()
+            .filter(|c| self.nodes[*c].games >= self.min_games)
+    }
+
+    /// Writes the book to the `moves` table, replacing what was in it. Returns the number of
+    /// moves written. The table is replaced in a single transaction, so on error the book that
+    /// was there before is left as it was.
+    pub fn write_sqlite(&self, db_conn: &Connection) -> Result<usize, sqlite::Error> {
+        db_conn.execute("BEGIN;")?;
+        match self.replace_moves(db_conn) {
+            Ok(written) => {
+                db_conn.execute("COMMIT;")?;
+                Ok(written)
+            }
+            Err(err) => {
+                // The error that caused the rollback is the one worth reporting
+                let _ = db_conn.execute("ROLLBACK;");
+                Err(err)
+            }
+        }
+    }
+
+    // Replaces the moves table with the book, inside the transaction opened by the caller
+    fn replace_moves(&self, db_conn: &Connection) -> Result<usize, sqlite::Error> {
+        db_conn.execute(
+            "DROP TABLE IF EXISTS moves;
+             CREATE TABLE moves (id INTEGER PRIMARY KEY, parent_move INTEGER, san TEXT, \
+             eval REAL, frequency INTEGER, terminal INTEGER, wins INTEGER, draws INTEGER, \
+             losses INTEGER);",
+        )?;
+        let mut stm = db_conn.prepare(
+            "INSERT INTO moves VALUES (:id, :parent_move, :san, :eval, :frequency, :terminal, \
+             :wins, :draws, :losses)",
+        )?;
+
+        // Ids are handed out breadth first, the root keeps its usual id
+        let mut queue = vec![(0, BOOK_ROOT, 0)];
+        let mut next_id = BOOK_ROOT + 1;
+        let mut written = 0;
+        while !queue.is_empty() {
+            for (node, id, parent_id) in mem::take(&mut queue) {
+                let n = &self.nodes[node];
+                let mover = match n.board.side_to_move() {
+                    Colour::White => Colour::Black,
+                    Colour::Black => Colour::White,
+                };
+                let terminal = self.kept_children(node).next().is_none();
+
+                stm.reset()?;
+                stm.bind((":id", id))?;
+                stm.bind((":parent_move", parent_id))?;
+                stm.bind((":san", n.san.as_str()))?;
+                stm.bind((":eval", n.eval(mover)))?;
+                stm.bind((":frequency", n.games))?;
+                stm.bind((":terminal", terminal as i64))?;
+                stm.bind((":wins", n.wins))?;
+                stm.bind((":draws", n.draws))?;
+                stm.bind((":losses", n.losses))?;
+                stm.next()?;
+                written += 1;
+
+                for child in self.kept_children(node) {
+                    queue.push((child, next_id, id));
+                    next_id += 1;
+                }
+            }
+        }
+        Ok(written)
+    }
+
+    /// The book in the Polyglot format. Moves are weighted by their results, two points for a
+    /// win and one for a draw, as Polyglot itself does.
+    pub fn polyglot(&self) -> PolyglotBook {
+        let mut weights: HashMap<(u64, u16), u32> = HashMap::new();
+        let mut stack = vec![0];
+        while let Some(node) = stack.pop() {
+            let board = self.nodes[node].board;
+            for child in self.kept_children(node) {
+                let c = &self.nodes[child];
+                if let Some(mv) = LanMove::from_boards(&board, &c.board) {
+                    // Transpositions add up
+                    *weights
+                        .entry((polyglot_key(&board), encode_move(mv, &board)))
+                        .or_default() += (2 * c.wins + c.draws) as u32;
+                }
+                stack.push(child);
+            }
+        }
+
+        PolyglotBook::from_entries(
+            weights
+                .into_iter()
+                .map(|((key, mv), weight)| PolyglotEntry {
+                    key,
+                    mv,
+                    weight: weight.min(u16::MAX as u32) as u16,
+                    learn: 0,
+                })
+                .collect(),
+        )
+    }
+}
+
+// Splits a PGN text into the SAN moves and the result of every game. Comments, variations,
+// move numbers and annotations are dropped.
+fn parse_pgn(pgn: &str) -> Vec<(Vec<String>, GameResult)> {
+    let mut games = Vec::new();
+    let mut movetext = String::new();
+    let mut result = GameResult::Unknown;
+    for line in pgn.lines() {
+        let line = line.trim();
+        if line.starts_with('[') {
+            // Tags after movetext start the next game
+            if !movetext.trim().is_empty() {
+                games.push((movetext_sans(&movetext, &mut result), result));
+                (movetext, result) = (String::new(), GameResult::Unknown);
+            }
+            if let Some(value) = line.strip_prefix("[Result \"") {
+                result = GameResult::parse(value.trim_end_matches("\"]")).unwrap_or(result);
+            }
+        } else {
+            // Rest of line comments
+            movetext.push_str(line.split(';').next().unwrap_or(""));
+            movetext.push(' ');
+        }
+    }
+    if !movetext.trim().is_empty() {
+        games.push((movetext_sans(&movetext, &mut result), result));
+    }
+    games
+}
+
+// The result at the end of the movetext is only used when there was no result tag
+fn movetext_sans(movetext: &str, result: &mut GameResult) -> Vec<String> {
+    let mut sans = Vec::new();
+    let mut token = String::new();
+    let mut in_comment = false;
+    let mut variation_depth = 0usize;
+    for c in movetext.chars().chain(iter::once(' ')) {
+        match c {
+            '}' if in_comment => in_comment = false,
+            _ if in_comment => (),
+            '{' => in_comment = true,
+            '(' => variation_depth += 1,
+            ')' => variation_depth = variation_depth.saturating_sub(1),
+            _ if variation_depth > 0 => (),
+            c if c.is_whitespace() => {
+                if let Some(game_result) = GameResult::parse(&token) {
+                    if *result == GameResult::Unknown {
+                        *result = game_result;
+                    }
+                } else {
+                    let san = strip_move_number(&token);
+                    if !san.is_empty() && !san.starts_with('$') {
+                        // Some databases castle with zeros
+                        let san = if san.starts_with("0-0") {
+                            san.replace('0', "O")
+                        } else {
+                            san.to_owned()
+                        };
+                        sans.push(san);
+                    }
+                }
+                token.clear();
+            }
+            c => token.push(c),
+        }
+    }
+    sans
+}
+
+// The token without the move number in front of it, "12." or "12...", if there is one
+fn strip_move_number(token: &str) -> &str {
+    let digits = token.len() - token.trim_start_matches(|c: char| c.is_ascii_digit()).len();
+    match token[digits..].trim_start_matches('.') {
+        rest if digits > 0 && rest.len() < token.len() - digits => rest,
+        _ => token,
+    }
+}
@@ -0,0 +1,518 @@
+use std::{collections::HashMap, env, error::Error, fmt, fs, io, path::Path};
+
+use rand::{distributions::WeightedIndex, rngs::StdRng, seq::SliceRandom, Rng, SeedableRng};
+use sqlite::{Connection, State, Statement};
+
+use chess_backend::{Board, Colour};
+
+use crate::engine::utils::phase::GamePhase;
+use polyglot::{polyglot_key, PolyglotBook};
+
+pub mod builder;
+pub mod polyglot;
+
+/// Id of the book entry for the starting position.
+pub const BOOK_ROOT: i64 = 1;
+/// Moves that were played fewer times than this are too rare to trust.
+pub const MIN_FREQUENCY: i64 = 3;
+// Columns every sqlite book has, the results columns are optional
+const BOOK_COLUMNS: [&str; 6] = ["id", "parent_move", "san", "eval", "frequency", "terminal"];
+// How many pawns of evaluation make a move e times as likely with `BookPolicy::EvalWeighted`
+const EVAL_TEMPERATURE: f64 = 0.25;
+
+/// How a move is chosen among the book moves of a position.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum BookPolicy {
+    /// Always the most frequently played move
+    #[default]
+    BestByFrequency,
+    /// A random move, weighted by how often each was played
+    FrequencyWeighted,
+    /// A random move, where better evaluated moves for the side to move are more likely
+    EvalWeighted,
+    /// Any move played at least this many times, all equally likely. Results recorded in the
+    /// book make no difference.
+    UniformAbove(i64),
+}
+
+/// Why an opening book could not be opened.
+#[derive(Debug)]
+pub enum BookError {
+    /// The file could not be read, or is not a valid Polyglot book
+    Io(io::Error),
+    /// The database could not be opened or queried
+    Sqlite(sqlite::Error),
+    /// The database has no `moves` table
+    MissingTable,
+    /// The `moves` table lacks a column every book needs
+    MissingColumn(&'static str),
+}
+impl fmt::Display for BookError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(e) => write!(f, "{e}"),
+            Self::Sqlite(e) => write!(f, "{e}"),
+            Self::MissingTable => write!(f, "the database has no moves table"),
+            Self::MissingColumn(column) => write!(f, "the moves table has no {column} column"),
+        }
+    }
+}
+impl Error for BookError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::Io(e) => Some(e),
+            Self::Sqlite(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+impl From<io::Error> for BookError {
+    fn from(e: io::Error) -> Self {
+        Self::Io(e)
+    }
+}
+impl From<sqlite::Error> for BookError {
+    fn from(e: sqlite::Error) -> Self {
+        Self::Sqlite(e)
+    }
+}
+
+#[derive(Debug, Clone)]
+struct BookMove {
+    pub id: i64,
+    pub parent_move: i64,
+    pub san: String,
+    pub eval: f64,
+    pub freq: i64,
+    pub terminal: bool,
+    pub score: f64,
+}
+impl BookMove {
+    // The book has nothing to say about the position after a terminal move
+    fn next_phase(&self) -> GamePhase {
+        if self.terminal {
+            GamePhase::Opening(None)
+        } else {
+            GamePhase::Opening(Some(self.id))
+        }
+    }
+}
+
+// Ids of the book entries that lead to a position, by the Polyglot key of the position. Unlike
+// the engine's own hash it ignores en passant squares that make no difference, which would
+// otherwise keep move orders like 1.d4 Nf6 2.c4 and 1.c4 Nf6 2.d4 apart.
+type PositionIndex = HashMap<u64, Vec<i64>>;
+
+// Where the book moves come from
+enum BookSource {
+    // A tree of moves linked by `parent_move`, walked with the id in `GamePhase::Opening`. With an
+    // index, positions are looked up by their hash instead.
+    Database(Connection, Option<PositionIndex>),
+    // Keyed by position, the id in `GamePhase::Opening` is carried along unchanged
+    Polyglot(PolyglotBook),
+    // No book, every position is searched
+    Empty,
+}
+
+/// Opening book, either stored in an sqlite database or in a Polyglot file. An empty book lets the
+/// engine play without one.
+pub struct OpeningBook {
+    source: BookSource,
+    policy: BookPolicy,
+    rng: StdRng,
+}
+impl OpeningBook {
+    pub fn new(db_conn: Connection) -> Self {
+        Self::from_source(BookSource::Database(db_conn, None))
+    }
+
+    pub fn polyglot(book: PolyglotBook) -> Self {
+        Self::from_source(BookSource::Polyglot(book))
+    }
+
+    pub fn empty() -> Self {
+        Self::from_source(BookSource::Empty)
+    }
+
+    /// Opens a book file, as a Polyglot book if it ends in `.bin` and as an sqlite database
+    /// otherwise. Databases are checked to have the columns of a book.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, BookError> {
+        let path = path.as_ref();
+        if path.extension().is_some_and(|ext| ext == "bin") {
+            return Self::open_polyglot(path);
+        }
+
+        // Opening a database that does not exist would create an empty one
+        fs::metadata(path)?;
+        let db_conn = Connection::open(path)?;
+        check_schema(&db_conn)?;
+        Ok(Self::new(db_conn))
+    }
+
+    /// Opens a Polyglot book file.
+    pub fn open_polyglot(path: impl AsRef<Path>) -> Result<Self, BookError> {
+        Ok(Self::polyglot(PolyglotBook::open(path)?))
+    }
+
+    /// The first book found at `path` in the working directory or next to the executable, or an
+    /// empty book if there is none that can be opened.
+    pub fn find(path: impl AsRef<Path>) -> Self {
+        let path = path.as_ref();
+        let beside_exe = env::current_exe()
+            .ok()
+            .and_then(|exe| Some(exe.parent()?.join(path)));
+        [Some(path.to_owned()), beside_exe]
+            .into_iter()
+            .flatten()
+            .find_map(|path| Self::open(path).ok())
+            .unwrap_or_else(Self::empty)
+    }
+
+    /// Whether the book has no moves at all, as when the engine plays without one.
+    pub fn is_empty(&self) -> bool {
+        match &self.source {
+            BookSource::Polyglot(book) => book.is_empty(),
+            BookSource::Database(..) => false,
+            BookSource::Empty => true,
+        }
+    }
+
+    fn from_source(source: BookSource) -> Self {
+        Self {
+            source,
+            policy: BookPolicy::default(),
+            rng: StdRng::from_entropy(),
+        }
+    }
+
+    pub fn set_policy(&mut self, policy: BookPolicy) {
+        self.policy = policy;
+    }
+
+    /// Makes the random choices of the book reproducible.
+    pub fn seed(&mut self, seed: u64) {
+        self.rng = StdRng::seed_from_u64(seed);
+    }
+
+    /// Looks positions up by their hash instead of by the moves that led to them, so that
+    /// transpositions into the book and positions set up from a FEN are recognised. The whole
+    /// book is replayed once to build the index. Polyglot books are always keyed by position.
+    pub fn set_position_lookup(&mut self, enabled: bool) -> Result<(), sqlite::Error> {
+        if let BookSource::Database(db_conn, index) = &mut self.source {
+            *index = if enabled {
+                Some(build_index(db_conn)?)
+            } else {
+                None
+            };
+        }
+        Ok(())
+    }
+
+    /// The phase of a position that was not reached by following the book, if the book knows it.
+    pub fn probe(&self, board: &Board) -> Option<GamePhase> {
+        match &self.source {
+            BookSource::Database(_, Some(index)) => index
+                .get(&polyglot_key(board))
+                .map(|ids| GamePhase::Opening(Some(ids[0]))),
+            BookSource::Database(_, None) => None,
+            BookSource::Polyglot(book) => (!book.entries(polyglot_key(board)).is_empty())
+                .then_some(GamePhase::Opening(Some(BOOK_ROOT))),
+            BookSource::Empty => None,
+        }
+    }
+
+    /// Picks a move from the book for `board`, the position of book entry `id`. Returns the
+    /// position after the move and the phase it is in, or None if the position has to be
+    /// searched instead.
+    pub fn find_bookmove(&mut self, board: &Board, id: i64) -> Option<(Board, GamePhase)> {
+        let side = board.side_to_move();
+        match &self.source {
+            BookSource::Database(db_conn, index) => {
+                let children = match index {
+                    Some(index) => {
+                        let ids = index.get(&polyglot_key(board))?;
+                        let children: Result<Vec<_>, _> =
+                            ids.iter().map(|id| find_children(db_conn, *id)).collect();
+                        merge_transpositions(children.ok()?.concat())
+                    }
+                    None => find_children(db_conn, id).ok()?,
+                };
+                let weights: Vec<i64> = children.iter().map(|bm| bm.freq).collect();
+                let evals: Vec<f64> = children.iter().map(|bm| bm.eval).collect();
+                let scores: Vec<f64> = children.iter().map(|bm| bm.score).collect();
+                let chosen = &children[choose(
+                    &weights,
+                    Some(&evals),
+                    Some(&scores),
+                    MIN_FREQUENCY,
+                    self.policy,
+                    side,
+                    &mut self.rng,
+                )?];
+                let next = board_after(board, &chosen.san)?;
+
+                Some((next, chosen.next_phase()))
+            }
+            BookSource::Polyglot(book) => {
+                let moves = book.moves(board);
+                let weights: Vec<i64> = moves.iter().map(|(_, _, w)| *w as i64).collect();
+                // Moves with no weight are in the book only to be recognised, not to be played
+                let chosen = choose(&weights, None, None, 1, self.policy, side, &mut self.rng)?;
+
+                Some((moves[chosen].1, GamePhase::Opening(Some(id))))
+            }
+            BookSource::Empty => None,
+        }
+    }
+
+    /// The phase after the move from `board` to `next` has been played, where `board` is the
+    /// position of book entry `id`. Moves the book does not know lead out of it.
+    pub fn follow_move(&self, board: &Board, id: i64, next: &Board) -> GamePhase {
+        match &self.source {
+            BookSource::Database(_, Some(index)) => index
+                .get(&polyglot_key(next))
+                .map_or(GamePhase::Opening(None), |ids| {
+                    GamePhase::Opening(Some(ids[0]))
+                }),
+            BookSource::Database(db_conn, None) => find_move(db_conn, &[id], board, next)
+                .map_or(GamePhase::Opening(None), |bm| bm.next_phase()),
+            BookSource::Polyglot(book) => {
+                if book.entries(polyglot_key(next)).is_empty() {
+                    GamePhase::Opening(None)
+                } else {
+                    GamePhase::Opening(Some(id))
+                }
+            }
+            BookSource::Empty => GamePhase::Opening(None),
+        }
+    }
+
+    /// Id of the book entry for the move from `board` to `next`, where `board` is the position of
+    /// book entry `id`. Polyglot entries have no ids.
+    pub fn entry_id(&self, board: &Board, id: i64, next: &Board) -> Option<i64> {
+        match &self.source {
+            BookSource::Database(db_conn, Some(index)) => {
+                find_move(db_conn, index.get(&polyglot_key(board))?, board, next)
+            }
+            BookSource::Database(db_conn, None) => find_move(db_conn, &[id], board, next),
+            BookSource::Polyglot(_) | BookSource::Empty => None,
+        }
+        .map(|bm| bm.id)
+    }
+
+    /// Prepares the book for `learn`, adding the columns for results to books that lack them.
+    pub fn enable_learning(&self) -> Result<(), sqlite::Error> {
+        match &self.source {
+            BookSource::Database(db_conn, _) => add_result_columns(db_conn),
+            BookSource::Polyglot(_) | BookSource::Empty => Ok(()),
+        }
+    }
+
+    /// Adds the result of a finished game, given by its winner or None for a draw, to the book
+    /// entries `moves`, each given with the side that played it. The results make moves that did
+    /// well more likely to be chosen and moves that did badly less likely. Polyglot books do not
+    /// learn.
+    pub fn learn(
+        &self,
+        moves: &[(i64, Colour)],
+        winner: Option<Colour>,
+    ) -> Result<(), sqlite::Error> {
+        let BookSource::Database(db_conn, _) = &self.source else {
+            return Ok(());
+        };
+        add_result_columns(db_conn)?;
+        for (id, side) in moves {
+            let column = match winner {
+                None => "draws",
+                Some(winner) if winner == *side => "wins",
+                Some(_) => "losses",
+            };
+            let mut stm = db_conn.prepare(format!(
+                "UPDATE moves SET {column} = {column} + 1 WHERE id = :id"
+            ))?;
+            stm.bind((":id", *id))?;
+            stm.next()?;
+        }
+        Ok(())
+    }
+}
+
+// Picks the index of a move given how often each was played and, if known, their evaluations
+// and how well they scored. Moves played less than `min_weight` times are only considered by
+// `BookPolicy::UniformAbove`, which has a threshold of its own.
+fn choose(
+    weights: &[i64],
+    evals: Option<&[f64]>,
+    scores: Option<&[f64]>,
+    min_weight: i64,
+    policy: BookPolicy,
+    side: Colour,
+    rng: &mut impl Rng,
+) -> Option<usize> {
+    let min_weight = match policy {
+        BookPolicy::UniformAbove(threshold) => threshold,
+        _ => min_weight,
+    };
+    let candidates: Vec<usize> = (0..weights.len())
+        .filter(|i| weights[*i] >= min_weight)
+        .collect();
+    let score = |i: usize| scores.map_or(1., |scores| scores[i]);
+    let weight = |i: usize| weights[i] as f64 * score(i);
+
+    match (policy, evals) {
+        (BookPolicy::BestByFrequency, _) => candidates
+            .into_iter()
+            .max_by(|i, j| weight(*i).total_cmp(&weight(*j))),
+        (BookPolicy::EvalWeighted, Some(evals)) => {
+            // Evaluations are stored from white's point of view
+            let sign = match side {
+                Colour::White => 1.,
+                Colour::Black => -1.,
+            };
+            let best = candidates
+                .iter()
+                .map(|i| sign * evals[*i])
+                .fold(f64::NEG_INFINITY, f64::max);
+            let dist = candidates
+                .iter()
+                .map(|i| ((sign * evals[*i] - best) / EVAL_TEMPERATURE).exp() * score(*i));
+            let dist = WeightedIndex::new(dist).ok()?;
+            Some(candidates[rng.sample(dist)])
+        }
+        // Without evaluations, how often a move was played is the best guess of how good it is
+        (BookPolicy::FrequencyWeighted | BookPolicy::EvalWeighted, _) => {
+            let dist = WeightedIndex::new(candidates.iter().map(|i| weight(*i))).ok()?;
+            Some(candidates[rng.sample(dist)])
+        }
+        (BookPolicy::UniformAbove(_), _) => candidates.choose(rng).copied(),
+    }
+}
+
+// The book move from `board` to `next` among the children of the entries `ids`
+fn find_move(db_conn: &Connection, ids: &[i64], board: &Board, next: &Board) -> Option<BookMove> {
+    let san = board.get_san(next);
+    ids.iter()
+        .flat_map(|id| find_children(db_conn, *id).unwrap_or_default())
+        .find(|bm| same_san(&bm.san, &san))
+}
+
+fn find_children(db_conn: &Connection, id: i64) -> Result<Vec<BookMove>, sqlite::Error> {
+    let mut stm = db_conn.prepare("SELECT * FROM moves WHERE parent_move = :id")?;
+    stm.bind((":id", id))?;
+    read_moves(&mut stm)
+}
+
+// Replays the book from the starting position to find the position every entry leads to. Terminal
+// entries are left out, as the book has no moves for the positions after them.
+fn build_index(db_conn: &Connection) -> Result<PositionIndex, sqlite::Error> {
+    let mut children: HashMap<i64, Vec<BookMove>> = HashMap::new();
+    for bm in read_moves(&mut db_conn.prepare("SELECT * FROM moves")?)? {
+        children.entry(bm.parent_move).or_default().push(bm);
+    }
+
+    let mut index = PositionIndex::new();
+    let mut stack = vec![(BOOK_ROOT, Board::default())];
+    while let Some((id, board)) = stack.pop() {
+        index.entry(polyglot_key(&board)).or_default().push(id);
+        for child in children.get(&id).into_iter().flatten() {
+            if let (false, Some(next)) = (child.terminal, board_after(&board, &child.san)) {
+                stack.push((child.id, next));
+            }
+        }
+    }
+    Ok(index)
+}
+
+// The same move from a position is stored once for every way of reaching the position
+fn merge_transpositions(moves: Vec<BookMove>) -> Vec<BookMove> {
+    let mut merged: Vec<BookMove> = Vec::new();
+    for bm in moves {
+        match merged.iter_mut().find(|m| same_san(&m.san, &bm.san)) {
+            Some(m) => {
+                let freq = m.freq + bm.freq;
+                if freq > 0 {
+                    m.eval = (m.eval * m.freq as f64 + bm.eval * bm.freq as f64) / freq as f64;
+                    m.score = (m.score * m.freq as f64 + bm.score * bm.freq as f64) / freq as f64;
+                }
+                m.freq = freq;
+                m.terminal &= bm.terminal;
+            }
+            None => merged.push(bm),
+        }
+    }
+    merged
+}
+
+fn read_moves(stm: &mut Statement) -> Result<Vec<BookMove>, sqlite::Error> {
+    let mut moves = Vec::new();
+    while let State::Row = stm.next()? {
+        moves.push(BookMove {
+            id: stm.read::<i64, _>("id")?,
+            parent_move: stm.read::<i64, _>("parent_move")?,
+            san: stm.read::<String, _>("san")?,
+            eval: stm.read::<f64, _>("eval")?,
+            freq: stm.read::<i64, _>("frequency")?,
+            terminal: stm.read::<i64, _>("terminal")? == 1,
+            // Books without results score every move the same
+            score: performance(
+                ["wins", "draws", "losses"].map(|column| stm.read::<i64, _>(column).unwrap_or(0)),
+            ),
+        });
+    }
+    Ok(moves)
+}
+
+// How well a move did, from 0 for nothing but losses to 2 for nothing but wins. One win and one
+// loss are added, so that a few results only nudge it away from 1.
+fn performance([wins, draws, losses]: [i64; 3]) -> f64 {
+    2. * (wins as f64 + draws as f64 / 2. + 1.) / (wins + draws + losses + 2) as f64
+}
+
+fn table_columns(db_conn: &Connection) -> Result<Vec<String>, sqlite::Error> {
+    let mut stm = db_conn.prepare("PRAGMA table_info(moves)")?;
+    let mut columns = Vec::new();
+    while let State::Row = stm.next()? {
+        columns.push(stm.read::<String, _>("name")?);
+    }
+    Ok(columns)
+}
+
+fn check_schema(db_conn: &Connection) -> Result<(), BookError> {
+    let columns = table_columns(db_conn)?;
+    if columns.is_empty() {
+        return Err(BookError::MissingTable);
+    }
+    match BOOK_COLUMNS
+        .into_iter()
+        .find(|column| !columns.iter().any(|c| c == column))
+    {
+        Some(column) => Err(BookError::MissingColumn(column)),
+        None => Ok(()),
+    }
+}
+
+fn add_result_columns(db_conn: &Connection) -> Result<(), sqlite::Error> {
+    let columns = table_columns(db_conn)?;
+    for column in ["wins", "draws", "losses"] {
+        if !columns.iter().any(|c| c == column) {
+            db_conn.execute(format!(
+                "ALTER TABLE moves ADD COLUMN {column} INTEGER NOT NULL DEFAULT 0"
+            ))?;
+        }
+    }
+    Ok(())
+}
+
+fn board_after(board: &Board, san: &str) -> Option<Board> {
+    board
+        .generate_legal_moves()
+        .into_iter()
+        .map(|m| m.board)
+        .find(|next| same_san(&board.get_san(next), san))
+}
+
+// Check and annotation marks are not part of the move itself
+fn same_san(san1: &str, san2: &str) -> bool {
+    let strip = |san: &str| san.trim_end_matches(['+', '#', '!', '?']).to_owned();
+    strip(san1) == strip(san2)
+}
@@ -0,0 +1,60 @@
+use std::time::Duration;
+
+use chess_backend::Board;
+
+use crate::engine::utils::{eval::Eval, lan::LanMove};
+
+/// The line the engine expects to be played from the searched position, its own move first,
+/// with what the search it came from looked like.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PrincipalVariation {
+    pub moves: Vec<LanMove>,
+    /// The same moves in SAN.
+    pub san: Vec<String>,
+    /// Evaluation of the line from white's point of view, None for book moves.
+    pub eval: Option<Eval>,
+    /// Depth of the last completed iteration.
+    pub depth: usize,
+    /// Deepest ply reached, captures resolved by the quiescence search included.
+    pub seldepth: usize,
+    /// Nodes searched by all threads together.
+    pub nodes: u64,
+    pub time: Duration,
+}
+impl PrincipalVariation {
+    /// The variation through the given positions, starting from `board`. Everything but the
+    /// moves is left to the caller to fill in.
+    pub fn from_line(board: &Board, line: &[Board]) -> Self {
+        let mut moves = Vec::with_capacity(line.len());
+        let mut san = Vec::with_capacity(line.len());
+        let mut before = board;
+        for after in line {
+            // A position that does not follow from the one before ends the line
+            let Some(mv) = LanMove::from_boards(before, after) else {
+                break;
+            };
+            moves.push(mv);
+            san.push(before.get_san(after));
+            before = after;
+        }
+        Self {
+            moves,
+            san,
+            ..Self::default()
+        }
+    }
+
+    /// The moves in UCI notation, separated by spaces.
+    pub fn uci(&self) -> String {
+        let moves: Vec<String> = self.moves.iter().map(LanMove::to_string).collect();
+        moves.join(" ")
+    }
+
+    /// Nodes searched per second.
+    pub fn nps(&self) -> u64 {
+        match self.time.as_millis() {
+            0 => 0,
+            millis => self.nodes * 1000 / millis as u64,
+        }
+    }
+}
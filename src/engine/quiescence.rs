@@ -0,0 +1,133 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use chess_backend::{Board, Colour, FinishedState, GameState, Pieces};
+
+use crate::engine::heuristics::see::see_ge;
+use crate::engine::move_ordering::order_tactical;
+use crate::engine::tree::{count_node, Node};
+use crate::engine::utils::eval::Eval;
+
+// Hard limit on the length of a capture sequence, so a long series of checks cannot run away
+const MAX_QUIESCENCE_DEPTH: usize = 12;
+
+impl Node {
+    /// Continues the search from a leaf with captures and promotions only (every move when in
+    /// check) until the position is quiet. The nodes searched here are not kept in the tree.
+    pub fn quiescence(
+        &mut self,
+        ply: usize,
+        alpha: Eval,
+        beta: Eval,
+        maximize: bool,
+        stop: &AtomicBool,
+    ) -> Eval {
+        self.quiescence_inner(ply, 0, alpha, beta, maximize, stop)
+    }
+
+    fn quiescence_inner(
+        &mut self,
+        ply: usize,
+        quiescence_depth: usize,
+        alpha: Eval,
+        beta: Eval,
+        maximize: bool,
+        stop: &AtomicBool,
+    ) -> Eval {
+        if stop.load(Ordering::Relaxed) {
+            return Eval::DRAW;
+        }
+        // The first node is the leaf of the main search, which has counted it already
+        if quiescence_depth > 0 {
+            count_node(ply);
+        }
+
+        let mut children = self.generate_children();
+        let stand_pat = self.eval_position(children.len(), ply);
+        if children.is_empty() || quiescence_depth == MAX_QUIESCENCE_DEPTH {
+            return stand_pat;
+        }
+
+        // In check, standing pat is not an option and every evasion has to be tried
+        let evading = in_check(&self.board);
+        let (mut alpha, mut beta) = (alpha, beta);
+        let mut best = if evading {
+            if maximize {
+                Eval::NEG_INFINITY
+            } else {
+                Eval::INFINITY
+            }
+        } else if maximize {
+            if stand_pat >= beta {
+                return stand_pat;
+            }
+            alpha = alpha.max(stand_pat);
+            stand_pat
+        } else {
+            if stand_pat <= alpha {
+                return stand_pat;
+            }
+            beta = beta.min(stand_pat);
+            stand_pat
+        };
+
+        order_tactical(&self.board, &mut children);
+        let board = self.board;
+        // Captures that lose material in the exchange are not worth looking at when standing pat
+        // is an option
+        for child in children.iter_mut().filter(|c| {
+            evading || is_tactical(&board, &c.board) && c.lan.is_none_or(|mv| see_ge(&board, mv, 0))
+        }) {
+            let eval =
+                child.quiescence_inner(ply + 1, quiescence_depth + 1, alpha, beta, !maximize, stop);
+
+            if maximize {
+                best = best.max(eval);
+                alpha = alpha.max(eval);
+            } else {
+                best = best.min(eval);
+                beta = beta.min(eval);
+            }
+            if beta < alpha {
+                break;
+            }
+        }
+        best
+    }
+}
+
+/// Whether the move from `before` to `after` captures or promotes.
+pub fn is_tactical(before: &Board, after: &Board) -> bool {
+    let (mover_before, mover_after, opponent_before, opponent_after) = match before.side_to_move() {
+        Colour::White => (
+            Pieces::from(before.base.white),
+            Pieces::from(after.base.white),
+            Pieces::from(before.base.black),
+            Pieces::from(after.base.black),
+        ),
+        Colour::Black => (
+            Pieces::from(before.base.black),
+            Pieces::from(after.base.black),
+            Pieces::from(before.base.white),
+            Pieces::from(after.base.white),
+        ),
+    };
+    piece_total(&opponent_after) < piece_total(&opponent_before)
+        || mover_after.pawns.len() < mover_before.pawns.len()
+}
+
+fn piece_total(pieces: &Pieces) -> usize {
+    pieces.pawns.len()
+        + pieces.knights.len()
+        + pieces.bishops.len()
+        + pieces.rooks.len()
+        + pieces.queens.len()
+}
+
+/// Whether the side to move is in check. Asking for the game state as if there were no legal
+/// moves tells checkmate, meaning check, apart from stalemate.
+pub fn in_check(board: &Board) -> bool {
+    matches!(
+        board.get_unchecked_game_state(0),
+        GameState::Finished(FinishedState::Win(..))
+    )
+}
@@ -0,0 +1,75 @@
+use std::sync::{atomic::AtomicBool, mpsc::Sender};
+
+use chess_backend::Colour;
+
+use crate::engine::move_ordering::ORDERING_TABLES;
+use crate::engine::pv::PrincipalVariation;
+use crate::engine::transposition::TranspositionTable;
+use crate::engine::tree::{node_stats, reset_node_stats, Node, Tree};
+use crate::engine::SearchResult;
+
+// Helper threads skip some depths so that they do not all search the same depth at the same
+// time. Thread i skips a depth whenever (depth + SKIP_PHASE[i]) / SKIP_SIZE[i] is odd.
+const SKIP_SIZE: [usize; 20] = [1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 3, 3, 4, 4, 4, 4, 4, 4, 4, 4];
+const SKIP_PHASE: [usize; 20] = [0, 1, 0, 1, 2, 3, 0, 1, 2, 3, 4, 5, 0, 1, 2, 3, 4, 5, 6, 7];
+
+/// Messages from a search thread: the result of every completed iteration, then None once the
+/// thread has stopped, each with the number of nodes the thread has searched so far.
+pub type ThreadReport = (usize, Option<SearchResult>, u64);
+
+/// Whether the given search thread skips an iteration. The main thread, with id 0, searches
+/// every depth.
+pub fn skips_depth(thread_id: usize, depth: usize) -> bool {
+    if thread_id == 0 {
+        return false;
+    }
+    let i = (thread_id - 1) % SKIP_SIZE.len();
+    ((depth + SKIP_PHASE[i]) / SKIP_SIZE[i]) & 1 == 1
+}
+
+/// One thread of a Lazy SMP search. Every thread runs its own iterative deepening over the whole
+/// position in a tree of its own, and the threads only cooperate through the shared
+/// transposition table.
+pub fn search_thread(
+    thread_id: usize,
+    root: Node,
+    max_depth: usize,
+    memory_limit: usize,
+    tt: &TranspositionTable,
+    stop: &AtomicBool,
+    tx: &Sender<ThreadReport>,
+) {
+    let maximize = root.board.side_to_move() == Colour::White;
+    let mut tree = Tree::new(root.board, root.phase, memory_limit);
+    reset_node_stats();
+
+    ORDERING_TABLES.with_borrow_mut(|ordering| {
+        for depth in 1..=max_depth {
+            // The last depth is searched by everyone, so that every thread ends the search
+            if depth < max_depth && skips_depth(thread_id, depth) {
+                continue;
+            }
+            if tree.run_root(depth, maximize, ordering, tt, stop).is_none() {
+                break;
+            }
+            if let Some(best) = tree.get_best(Tree::ROOT, maximize) {
+                let (nodes, seldepth) = node_stats();
+                let pv = PrincipalVariation {
+                    eval: tree.node(best).eval,
+                    depth,
+                    seldepth,
+                    nodes,
+                    ..PrincipalVariation::from_line(
+                        &root.board,
+                        &tree.principal_variation(maximize),
+                    )
+                };
+                let res = SearchResult::from_node(tree.node(best), depth, pv);
+                tx.send((thread_id, Some(res), nodes))
+                    .expect("Failed to send search result");
+            }
+        }
+    });
+    tx.send((thread_id, None, node_stats().0))
+        .expect("Failed to send search result");
+}
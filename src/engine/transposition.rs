@@ -0,0 +1,182 @@
+use std::{
+    mem,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use crate::engine::utils::{
+    eval::Eval,
+    lan::{LanMove, Promotion},
+};
+
+pub const DEFAULT_SIZE_MB: usize = 64;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Bound {
+    Exact,
+    /// The real value is at least the stored one
+    Lower,
+    /// The real value is at most the stored one
+    Upper,
+}
+impl Bound {
+    /// What a fail-soft search result says about the real value, given the window it was
+    /// searched with.
+    pub fn from_window(eval: Eval, alpha: Eval, beta: Eval) -> Self {
+        if eval <= alpha {
+            Self::Upper
+        } else if eval >= beta {
+            Self::Lower
+        } else {
+            Self::Exact
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TtEntry {
+    pub depth: u8,
+    pub bound: Bound,
+    pub best_move: Option<LanMove>,
+    pub eval: Eval,
+}
+
+// Every slot stores the key xor'ed with the data. A slot torn by two threads writing at once will
+// then fail the key check instead of returning another position's data, which is what allows the
+// table to be shared without locks.
+#[derive(Debug, Default)]
+struct Slot {
+    key: AtomicU64,
+    data: AtomicU64,
+}
+
+/// Fixed-size hash table of search results, shared between all search threads.
+#[derive(Debug)]
+pub struct TranspositionTable {
+    slots: Box<[Slot]>,
+}
+impl TranspositionTable {
+    pub fn new(size_mb: usize) -> Self {
+        // Round down to a power of two so that indexing is a simple mask
+        let n_slots = (size_mb * 1024 * 1024 / mem::size_of::<Slot>()).max(1);
+        let n_slots = 1 << n_slots.ilog2();
+        Self {
+            slots: (0..n_slots).map(|_| Slot::default()).collect(),
+        }
+    }
+
+    fn slot(&self, hash: u64) -> &Slot {
+        &self.slots[hash as usize & (self.slots.len() - 1)]
+    }
+
+    pub fn probe(&self, hash: u64) -> Option<TtEntry> {
+        let slot = self.slot(hash);
+        let data = slot.data.load(Ordering::Relaxed);
+        let key = slot.key.load(Ordering::Relaxed);
+        if key ^ data == hash {
+            decode(data)
+        } else {
+            None
+        }
+    }
+
+    /// Stores an entry, replacing whatever was in its slot unless that is a deeper search of the
+    /// same position.
+    pub fn store(&self, hash: u64, entry: TtEntry) {
+        if let Some(existing) = self.probe(hash) {
+            if existing.depth > entry.depth {
+                return;
+            }
+        }
+        let slot = self.slot(hash);
+        let data = encode(entry);
+        slot.key.store(hash ^ data, Ordering::Relaxed);
+        slot.data.store(data, Ordering::Relaxed);
+    }
+
+    pub fn clear(&self) {
+        for slot in self.slots.iter() {
+            slot.key.store(0, Ordering::Relaxed);
+            slot.data.store(0, Ordering::Relaxed);
+        }
+    }
+}
+impl Default for TranspositionTable {
+    fn default() -> Self {
+        Self::new(DEFAULT_SIZE_MB)
+    }
+}
+
+// Layout of the data word
+const VALUE_BITS: u64 = 0xffff_ffff;
+const BOUND_SHIFT: u64 = 35;
+const DEPTH_SHIFT: u64 = 37;
+const MOVE_SHIFT: u64 = 45;
+const HAS_MOVE_BIT: u64 = 1 << 60;
+// Distinguishes an entry from an empty slot
+const VALID_BIT: u64 = 1 << 63;
+
+fn encode(entry: TtEntry) -> u64 {
+    let bound = match entry.bound {
+        Bound::Exact => 0,
+        Bound::Lower => 1,
+        Bound::Upper => 2,
+    };
+    let mv = match entry.best_move {
+        Some(mv) => {
+            let promotion = match mv.promotion {
+                None => 0,
+                Some(Promotion::Knight) => 1,
+                Some(Promotion::Bishop) => 2,
+                Some(Promotion::Rook) => 3,
+                Some(Promotion::Queen) => 4,
+            };
+            HAS_MOVE_BIT
+                | ((mv.origin as u64) | (mv.destination as u64) << 6 | promotion << 12)
+                    << MOVE_SHIFT
+        }
+        None => 0,
+    };
+
+    VALID_BIT
+        | entry.eval.value() as u32 as u64
+        | bound << BOUND_SHIFT
+        | (entry.depth as u64) << DEPTH_SHIFT
+        | mv
+}
+
+fn decode(data: u64) -> Option<TtEntry> {
+    if data & VALID_BIT == 0 {
+        return None;
+    }
+
+    // Every value an evaluation can take is in range, mate scores included
+    let eval = Eval::from_value((data & VALUE_BITS) as u32 as i32);
+    let bound = match (data >> BOUND_SHIFT) & 0b11 {
+        0 => Bound::Exact,
+        1 => Bound::Lower,
+        _ => Bound::Upper,
+    };
+    let best_move = if data & HAS_MOVE_BIT != 0 {
+        let mv = data >> MOVE_SHIFT;
+        Some(LanMove {
+            origin: (mv & 0x3f) as i32,
+            destination: ((mv >> 6) & 0x3f) as i32,
+            promotion: match (mv >> 12) & 0b111 {
+                1 => Some(Promotion::Knight),
+                2 => Some(Promotion::Bishop),
+                3 => Some(Promotion::Rook),
+                4 => Some(Promotion::Queen),
+                _ => None,
+            },
+        })
+    } else {
+        None
+    };
+
+    Some(TtEntry {
+        depth: ((data >> DEPTH_SHIFT) & 0xff) as u8,
+        bound,
+        best_move,
+        eval,
+    })
+}
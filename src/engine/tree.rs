@@ -1,232 +1,411 @@
-use std::mem;
+use std::{
+    cell::Cell,
+    iter, mem,
+    sync::atomic::{AtomicBool, Ordering},
+};
 
-use chess_backend::Board;
+use chess_backend::{Board, Colour};
 
+use crate::engine::move_ordering::OrderingTables;
+use crate::engine::quiescence::is_tactical;
+use crate::engine::transposition::{Bound, TranspositionTable, TtEntry};
 use crate::engine::utils::eval::Eval;
+use crate::engine::utils::lan::LanMove;
 use crate::engine::utils::phase::GamePhase;
+use crate::engine::zobrist;
+
+thread_local! {
+    // Nodes visited by the search of this thread and the deepest ply it reached, quiescence
+    // search included
+    static NODES: Cell<u64> = const { Cell::new(0) };
+    static SELDEPTH: Cell<usize> = const { Cell::new(0) };
+}
+
+/// Counts a node visited by the search of this thread, `ply` half moves from the root.
+pub fn count_node(ply: usize) {
+    NODES.set(NODES.get() + 1);
+    SELDEPTH.set(SELDEPTH.get().max(ply));
+}
+
+/// The nodes counted by this thread and the deepest ply reached since the last reset.
+pub fn node_stats() -> (u64, usize) {
+    (NODES.get(), SELDEPTH.get())
+}
+
+pub fn reset_node_stats() {
+    NODES.set(0);
+    SELDEPTH.set(0);
+}
+
+/// Index of a node in a `Tree`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct NodeId(u32);
 
 #[derive(Debug, Clone)]
-pub struct Branch {
+pub struct Node {
     pub board: Board,
+    pub lan: Option<LanMove>,
     pub eval: Option<Eval>,
     pub phase: Option<GamePhase>,
-    pub children: Vec<Branch>,
     pub is_terminal: bool,
+    parent: Option<NodeId>,
+    first_child: Option<NodeId>,
+    next_sibling: Option<NodeId>,
 }
-impl Branch {
-    pub fn populate(&mut self) {
-        self.children = self
-            .board
+impl Node {
+    /// Creates a new node that should inherit the game phase from its parent.
+    /// If the phase is None, it will be determined at the next evaluation
+    pub fn from_parent(board: Board, parent_phase: Option<GamePhase>) -> Self {
+        Self {
+            board,
+            lan: None,
+            eval: None,
+            phase: parent_phase,
+            is_terminal: false,
+            parent: None,
+            first_child: None,
+            next_sibling: None,
+        }
+    }
+
+    /// Creates a child node, remembering the move that was played on `parent` to reach `board`.
+    pub fn from_move(parent: &Board, board: Board, parent_phase: Option<GamePhase>) -> Self {
+        Self {
+            lan: LanMove::from_boards(parent, &board),
+            ..Self::from_parent(board, parent_phase)
+        }
+    }
+
+    /// The positions reachable in one move, as nodes that are not part of any tree yet.
+    pub fn generate_children(&self) -> Vec<Node> {
+        self.board
             .generate_legal_moves()
             .iter()
-            .map(|m| Branch::from_parent(m.board, self.phase))
-            .collect();
+            .map(|m| Node::from_move(&self.board, m.board, self.phase))
+            .collect()
     }
+}
 
-    fn simple_alpha_beta<'a>(
-        &'a mut self,
-        current_depth: usize,
-        desired_depth: usize,
-        current_location: &'a [usize],
-        alpha: Eval,
-        beta: Eval,
-        maximize: bool,
-    ) -> (Eval, Vec<usize>) {
-        self.populate();
-        if current_depth == desired_depth || self.children.len() == 0 {
-            let eval = self.eval_position(self.children.len(), current_depth);
-            self.eval = Some(eval);
-            self.is_terminal = true;
-            return (eval, current_location.into());
+/// Search tree kept in a single arena of nodes that link to each other by index. Nodes of
+/// subtrees that are no longer needed go to a free list to be reused, and the arena never holds
+/// more nodes than its memory limit allows.
+#[derive(Debug)]
+pub struct Tree {
+    nodes: Vec<Node>,
+    free: Vec<NodeId>,
+    capacity: usize,
+}
+impl Tree {
+    pub const ROOT: NodeId = NodeId(0);
+
+    /// Creates a tree for the given root position that uses at most about `memory_limit` bytes.
+    pub fn new(board: Board, phase: Option<GamePhase>, memory_limit: usize) -> Self {
+        Self {
+            nodes: vec![Node::from_parent(board, phase)],
+            free: Vec::new(),
+            capacity: (memory_limit / mem::size_of::<Node>()).clamp(1, u32::MAX as usize),
         }
+    }
 
-        if maximize {
-            let mut max_eval = Eval::NegInfinity;
-            let mut max_location = Vec::new();
-            let mut alpha = alpha;
-            for (relative_location, child) in &mut self.children.iter_mut().enumerate() {
-                let (eval, inherited_location) = child.simple_alpha_beta(
-                    current_depth + 1,
-                    desired_depth,
-                    &[current_location, &[relative_location]].concat(),
-                    alpha,
-                    beta,
-                    false,
-                );
-                if eval > max_eval {
-                    max_eval = eval;
-                    max_location = inherited_location.clone();
-                    self.eval = Some(max_eval);
-                }
-                alpha = alpha.max(eval);
-                if beta < alpha {
-                    break;
-                }
-            }
-            (max_eval, max_location)
+    pub fn node(&self, id: NodeId) -> &Node {
+        &self.nodes[id.0 as usize]
+    }
+
+    pub fn node_mut(&mut self, id: NodeId) -> &mut Node {
+        &mut self.nodes[id.0 as usize]
+    }
+
+    pub fn children(&self, id: NodeId) -> impl Iterator<Item = NodeId> + '_ {
+        iter::successors(self.node(id).first_child, move |child| {
+            self.node(*child).next_sibling
+        })
+    }
+
+    /// Number of nodes currently in the tree.
+    pub fn node_count(&self) -> usize {
+        self.nodes.len() - self.free.len()
+    }
+
+    /// Bytes held by the arena, including nodes waiting on the free list.
+    #[cfg(test)]
+    pub fn memory_usage(&self) -> usize {
+        self.nodes.capacity() * mem::size_of::<Node>()
+            + self.free.capacity() * mem::size_of::<NodeId>()
+    }
+
+    fn alloc(&mut self, node: Node) -> NodeId {
+        if let Some(id) = self.free.pop() {
+            *self.node_mut(id) = node;
+            id
         } else {
-            let mut min_eval = Eval::Infinity;
-            let mut min_location = Vec::new();
-            let mut beta = beta;
-            for (relative_location, child) in &mut self.children.iter_mut().enumerate() {
-                let (eval, inherited_location) = child.simple_alpha_beta(
-                    current_depth + 1,
-                    desired_depth,
-                    &[current_location, &[relative_location]].concat(),
-                    alpha,
-                    beta,
-                    true,
-                );
-                if eval < min_eval {
-                    min_eval = eval;
-                    min_location = inherited_location.clone();
-                    self.eval = Some(min_eval);
-                }
-                beta = beta.min(eval);
-                if beta < alpha {
-                    break;
-                }
+            self.nodes.push(node);
+            NodeId((self.nodes.len() - 1) as u32)
+        }
+    }
+
+    /// Replaces the children of a node with `children`, linked in the order given. Returns false
+    /// if the arena has no room for them, in which case the node is left without children.
+    pub fn expand(&mut self, id: NodeId, children: Vec<Node>) -> bool {
+        self.prune_children(id, None);
+        if self.node_count() + children.len() > self.capacity {
+            return false;
+        }
+
+        let mut previous: Option<NodeId> = None;
+        for mut child in children {
+            child.parent = Some(id);
+            let child_id = self.alloc(child);
+            match previous {
+                Some(previous) => self.node_mut(previous).next_sibling = Some(child_id),
+                None => self.node_mut(id).first_child = Some(child_id),
             }
-            (min_eval, min_location)
+            previous = Some(child_id);
         }
+        true
     }
 
-    pub fn run_node<'a>(
-        &'a mut self,
-        depth: usize,
-        location: &'a [usize],
-        maximize: bool,
-    ) -> [Option<(Eval, Vec<usize>)>; 3] {
-        self.is_terminal = false;
-        self.simple_alpha_beta(
-            0,
-            depth,
-            location,
-            Eval::NegInfinity,
-            Eval::Infinity,
-            maximize,
-        );
-        self.get_top_three(location, maximize)
+    /// Frees every child of a node except `keep`, along with their subtrees.
+    pub fn prune_children(&mut self, id: NodeId, keep: Option<NodeId>) {
+        let children: Vec<NodeId> = self.children(id).collect();
+        for child in children.into_iter().filter(|c| Some(*c) != keep) {
+            self.free_subtree(child);
+        }
+        self.node_mut(id).first_child = keep;
+        if let Some(keep) = keep {
+            self.node_mut(keep).next_sibling = None;
+        }
     }
 
-    pub fn get_top_three(
-        &self,
-        location: &[usize],
+    fn free_subtree(&mut self, id: NodeId) {
+        let mut stack = vec![id];
+        while let Some(id) = stack.pop() {
+            stack.extend(self.children(id));
+            let node = self.node_mut(id);
+            node.first_child = None;
+            node.next_sibling = None;
+            self.free.push(id);
+        }
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn simple_alpha_beta(
+        &mut self,
+        id: NodeId,
+        current_depth: usize,
+        desired_depth: usize,
+        alpha: Eval,
+        beta: Eval,
         maximize: bool,
-    ) -> [Option<(Eval, Vec<usize>)>; 3] {
-        let mut top_three: [Option<(Eval, Vec<usize>)>; 3] = [None, None, None];
-        for (relative_location, child) in self.children.iter().enumerate() {
-            if let Some(eval) = child.eval {
-                if let Some(current_first_value) = top_three[0].clone() {
-                    if (maximize && eval > current_first_value.0)
-                        || (!maximize && eval < current_first_value.0)
-                    {
-                        top_three[2] = mem::take(&mut top_three[1]);
-                        top_three[1] = mem::take(&mut top_three[0]);
-                        top_three[0] = Some((eval, [location, &[relative_location]].concat()));
-                    } else if let Some(current_second_value) = top_three[1].clone() {
-                        if (maximize && eval > current_second_value.0)
-                            || (!maximize && eval < current_second_value.0)
-                        {
-                            top_three[2] = mem::take(&mut top_three[1]);
-                            top_three[1] = Some((eval, [location, &[relative_location]].concat()));
-                        } else if let Some(current_second_value) = top_three[2].clone() {
-                            if (maximize && eval > current_second_value.0)
-                                || (!maximize && eval < current_second_value.0)
-                            {
-                                top_three[2] =
-                                    Some((eval, [location, &[relative_location]].concat()));
-                            }
-                        } else {
-                            top_three[2] = Some((eval, [location, &[relative_location]].concat()));
-                        }
-                    } else {
-                        top_three[1] = Some((eval, [location, &[relative_location]].concat()));
+        ordering: &mut OrderingTables,
+        tt: &TranspositionTable,
+        stop: &AtomicBool,
+    ) -> Eval {
+        // The value returned after a stop is meaningless, callers have to check the flag
+        if stop.load(Ordering::Relaxed) {
+            return Eval::DRAW;
+        }
+        count_node(current_depth);
+
+        // Mate distance pruning. Nothing from here mates sooner than on the next ply, so once as
+        // short a mate is certain elsewhere there is nothing left to find.
+        if current_depth > 0 {
+            let winner = if maximize {
+                Colour::White
+            } else {
+                Colour::Black
+            };
+            let best_possible = Eval::mate(current_depth + 1, winner);
+            if (maximize && best_possible <= alpha) || (!maximize && best_possible >= beta) {
+                self.prune_children(id, None);
+                let node = self.node_mut(id);
+                node.eval = Some(best_possible);
+                node.is_terminal = true;
+                return best_possible;
+            }
+        }
+
+        let board = self.node(id).board;
+        let hash = zobrist::hash(&board);
+        let remaining_depth = (desired_depth - current_depth) as u8;
+        let (alpha_orig, beta_orig) = (alpha, beta);
+        let (mut alpha, mut beta) = (alpha, beta);
+        let mut hash_move = None;
+        if let Some(entry) = tt.probe(hash) {
+            hash_move = entry.best_move;
+            let eval = entry.eval.root_relative(current_depth);
+            // The root has to be searched in any case, a move is needed from it
+            if entry.depth >= remaining_depth && current_depth > 0 {
+                let cutoff = match entry.bound {
+                    Bound::Exact => true,
+                    Bound::Lower => {
+                        alpha = alpha.max(eval);
+                        eval >= beta
+                    }
+                    Bound::Upper => {
+                        beta = beta.min(eval);
+                        eval <= alpha
                     }
-                } else {
-                    top_three[0] = Some((eval, [location, &[relative_location]].concat()))
+                };
+                if cutoff {
+                    // Known well enough already, treat it like a leaf
+                    self.prune_children(id, None);
+                    let node = self.node_mut(id);
+                    node.eval = Some(eval);
+                    node.is_terminal = true;
+                    return eval;
                 }
             }
         }
 
-        top_three
-    }
-
-    // Doesn't evaluate positions, simply rearanges with new information
-    pub fn simple_minimax(&mut self, maximize: bool) -> Eval {
-        if self.is_terminal {
-            // Unwrap should be safe. All terminal nodes have been evaluated
-            self.eval.unwrap()
-        } else if maximize {
-            let mut max_eval = Eval::NegInfinity;
-            for child in &mut self.children {
-                let eval = child.simple_minimax(false);
-                max_eval = max_eval.max(eval);
-                self.eval = Some(max_eval);
+        // Leaves are ordered by the quiescence search instead
+        let mut children = Vec::new();
+        if current_depth < desired_depth {
+            children = self.node(id).generate_children();
+            ordering.order(&board, &mut children, hash_move, current_depth);
+        }
+        // Nodes the arena has no room for are resolved like leaves
+        let has_moves = !children.is_empty();
+        if !has_moves || !self.expand(id, children) {
+            self.prune_children(id, None);
+            // Captures are resolved before the position is trusted to be evaluated statically
+            let node = self.node_mut(id);
+            let eval = node.quiescence(current_depth, alpha, beta, maximize, stop);
+            node.eval = Some(eval);
+            node.is_terminal = true;
+            if !stop.load(Ordering::Relaxed) {
+                tt.store(
+                    hash,
+                    TtEntry {
+                        // A node cut short for lack of room was searched no deeper than a leaf
+                        depth: if has_moves { 0 } else { remaining_depth },
+                        bound: Bound::from_window(eval, alpha_orig, beta_orig),
+                        best_move: None,
+                        eval: eval.node_relative(current_depth),
+                    },
+                );
             }
-            max_eval
+            return eval;
+        }
+        self.node_mut(id).is_terminal = false;
+
+        let mut best_child = None;
+        let mut best_eval = if maximize {
+            Eval::NEG_INFINITY
         } else {
-            let mut min_eval = Eval::Infinity;
-            for child in &mut self.children {
-                let eval = child.simple_minimax(true);
-                min_eval = min_eval.min(eval);
-                self.eval = Some(min_eval);
+            Eval::INFINITY
+        };
+        let children: Vec<NodeId> = self.children(id).collect();
+        for child in children {
+            let eval = self.simple_alpha_beta(
+                child,
+                current_depth + 1,
+                desired_depth,
+                alpha,
+                beta,
+                !maximize,
+                ordering,
+                tt,
+                stop,
+            );
+            if (maximize && eval > best_eval) || (!maximize && eval < best_eval) {
+                best_eval = eval;
+                best_child = Some(child);
+                self.node_mut(id).eval = Some(best_eval);
+            }
+            if maximize {
+                alpha = alpha.max(eval);
+            } else {
+                beta = beta.min(eval);
+            }
+            if beta < alpha {
+                self.record_cutoff(ordering, child, current_depth, remaining_depth);
+                break;
             }
-            min_eval
         }
-    }
 
-    pub fn get_best(&mut self, maximize: bool) -> Option<&Branch> {
-        // fix tree after expanded search
-        self.simple_minimax(maximize);
-        if maximize {
-            self.children
-                .iter()
-                .max_by(|c1, c2| c1.eval.partial_cmp(&c2.eval).unwrap())
-        } else {
-            self.children
-                .iter()
-                .min_by(|c1, c2| c1.eval.partial_cmp(&c2.eval).unwrap())
+        if !stop.load(Ordering::Relaxed) {
+            tt.store(
+                hash,
+                TtEntry {
+                    depth: remaining_depth,
+                    bound: Bound::from_window(best_eval, alpha_orig, beta_orig),
+                    best_move: best_child.and_then(|c| self.node(c).lan),
+                    eval: best_eval.node_relative(current_depth),
+                },
+            );
         }
+        // Only the line the search expects to be played is worth keeping
+        self.prune_children(id, best_child);
+        best_eval
     }
 
-    pub fn find_branch(&self, location: &[usize]) -> &Self {
-        if location.len() == 0 {
-            self
-        } else {
-            self.children[location[0]].find_branch(&location[1..])
+    // Quiet moves that refute a position are likely to refute its siblings as well
+    fn record_cutoff(
+        &self,
+        ordering: &mut OrderingTables,
+        child: NodeId,
+        ply: usize,
+        remaining_depth: u8,
+    ) {
+        let child = self.node(child);
+        // Unwrap is safe, the child was reached from its parent
+        let parent = self.node(child.parent.unwrap());
+        if let Some(mv) = child.lan {
+            if !is_tactical(&parent.board, &child.board) {
+                ordering.record_cutoff(mv, ply, remaining_depth as usize);
+            }
         }
     }
 
-    pub fn insert_branch(&mut self, input_branch: Branch, location: &[usize]) {
-        if location.len() == 0 {
-            *self = input_branch;
+    /// Searches from the root to the given depth. Returns None if the search was stopped before
+    /// it could complete.
+    pub fn run_root(
+        &mut self,
+        depth: usize,
+        maximize: bool,
+        ordering: &mut OrderingTables,
+        tt: &TranspositionTable,
+        stop: &AtomicBool,
+    ) -> Option<Eval> {
+        let eval = self.simple_alpha_beta(
+            Self::ROOT,
+            0,
+            depth,
+            Eval::NEG_INFINITY,
+            Eval::INFINITY,
+            maximize,
+            ordering,
+            tt,
+            stop,
+        );
+        if stop.load(Ordering::Relaxed) {
+            None
         } else {
-            self.children[location[0]].insert_branch(input_branch, &location[1..]);
+            Some(eval)
         }
     }
 
-    pub fn show_branch(&self, depth: usize) {
-        for _ in 0..depth {
-            print!("|   ");
-        }
-        println!("{:?}", self.eval);
-        for child in &self.children {
-            if child.eval != None {
-                child.show_branch(depth + 1);
-            }
+    /// The child of `id` with the best evaluation for the side to move. Children that were
+    /// pruned before being searched have no evaluation and are skipped.
+    pub fn get_best(&self, id: NodeId, maximize: bool) -> Option<NodeId> {
+        let evaluated = self.children(id).filter(|c| self.node(*c).eval.is_some());
+        if maximize {
+            evaluated.max_by_key(|c| self.node(*c).eval)
+        } else {
+            evaluated.min_by_key(|c| self.node(*c).eval)
         }
     }
 
-    /// Creates a new branch that should inherit the game phase from its parent.
-    /// If the phase is None, it will be determined at the next evaluation
-    pub fn from_parent(board: Board, parent_phase: Option<GamePhase>) -> Self {
-        Self {
-            board,
-            eval: None,
-            phase: parent_phase,
-            children: Vec::new(),
-            is_terminal: false,
+    /// The positions along the line the search expects to be played, from the root down to where
+    /// the tree ends. Only the best child of a node is kept once it has been searched, so this is
+    /// the principal variation of the last search.
+    pub fn principal_variation(&self, maximize: bool) -> Vec<Board> {
+        let mut line = Vec::new();
+        let (mut id, mut maximize) = (Self::ROOT, maximize);
+        while let Some(best) = self.get_best(id, maximize) {
+            line.push(self.node(best).board);
+            (id, maximize) = (best, !maximize);
         }
+        line
     }
 }
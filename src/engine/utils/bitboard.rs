@@ -0,0 +1,116 @@
+use std::iter;
+
+use chess_backend::Colour;
+
+/// Squares of the a-file, with a1 = bit 0.
+pub const FILE_A: u64 = 0x0101_0101_0101_0101;
+pub const RANK_1: u64 = 0xff;
+
+const KNIGHT_STEPS: [(i32, i32); 8] = [
+    (1, 2),
+    (2, 1),
+    (2, -1),
+    (1, -2),
+    (-1, -2),
+    (-2, -1),
+    (-2, 1),
+    (-1, 2),
+];
+const KING_STEPS: [(i32, i32); 8] = [
+    (0, 1),
+    (1, 1),
+    (1, 0),
+    (1, -1),
+    (0, -1),
+    (-1, -1),
+    (-1, 0),
+    (-1, 1),
+];
+const BISHOP_DIRECTIONS: [(i32, i32); 4] = [(1, 1), (1, -1), (-1, -1), (-1, 1)];
+const ROOK_DIRECTIONS: [(i32, i32); 4] = [(0, 1), (1, 0), (0, -1), (-1, 0)];
+
+pub fn bitboard(squares: &[i32]) -> u64 {
+    squares.iter().fold(0, |bb, sq| bb | 1 << sq)
+}
+
+/// The squares of the set bits, from a1 upwards.
+pub fn squares(bb: u64) -> impl Iterator<Item = i32> {
+    iter::successors(Some(bb), |bb| Some(bb & (bb - 1)))
+        .take_while(|bb| *bb != 0)
+        .map(|bb| bb.trailing_zeros() as i32)
+}
+
+pub fn file_mask(file: i32) -> u64 {
+    FILE_A << file
+}
+
+pub fn adjacent_files(file: i32) -> u64 {
+    let left = if file > 0 { file_mask(file - 1) } else { 0 };
+    let right = if file < 7 { file_mask(file + 1) } else { 0 };
+    left | right
+}
+
+/// The rank, or nothing for ranks off the board.
+pub fn rank_mask(rank: i32) -> u64 {
+    if (0..8).contains(&rank) {
+        RANK_1 << (8 * rank)
+    } else {
+        0
+    }
+}
+
+// The squares one step each away from `sq`, if they are on the board
+fn steps(sq: i32, steps: &[(i32, i32)]) -> u64 {
+    let (file, rank) = (sq % 8, sq / 8);
+    steps
+        .iter()
+        .map(|(df, dr)| (file + df, rank + dr))
+        .filter(|(f, r)| (0..8).contains(f) && (0..8).contains(r))
+        .fold(0, |bb, (f, r)| bb | 1 << (r * 8 + f))
+}
+
+// The squares along each direction from `sq`, up to and including the first occupied one
+fn rays(sq: i32, occupied: u64, directions: &[(i32, i32)]) -> u64 {
+    let mut attacks = 0;
+    for (df, dr) in directions {
+        let (mut file, mut rank) = (sq % 8 + df, sq / 8 + dr);
+        while (0..8).contains(&file) && (0..8).contains(&rank) {
+            let bit = 1 << (rank * 8 + file);
+            attacks |= bit;
+            if occupied & bit != 0 {
+                break;
+            }
+            (file, rank) = (file + df, rank + dr);
+        }
+    }
+    attacks
+}
+
+pub fn knight_attacks(sq: i32) -> u64 {
+    steps(sq, &KNIGHT_STEPS)
+}
+
+pub fn king_attacks(sq: i32) -> u64 {
+    steps(sq, &KING_STEPS)
+}
+
+/// Every square attacked by the pawns of `colour`.
+pub fn pawn_attacks(pawns: u64, colour: Colour) -> u64 {
+    let (left, right) = (pawns & !FILE_A, pawns & !file_mask(7));
+    match colour {
+        Colour::White => left << 7 | right << 9,
+        Colour::Black => left >> 9 | right >> 7,
+    }
+}
+
+pub fn bishop_attacks(sq: i32, occupied: u64) -> u64 {
+    rays(sq, occupied, &BISHOP_DIRECTIONS)
+}
+
+pub fn rook_attacks(sq: i32, occupied: u64) -> u64 {
+    rays(sq, occupied, &ROOK_DIRECTIONS)
+}
+
+pub fn queen_attacks(sq: i32, occupied: u64) -> u64 {
+    bishop_attacks(sq, occupied) | rook_attacks(sq, occupied)
+}
@@ -1,151 +1,105 @@
 use chess_backend::Colour;
-use std::{
-    cmp::Ordering,
-    ops::{Add, Sub},
-};
+use std::ops::Neg;
 
-#[derive(Debug, PartialEq, Clone, Copy)]
-pub enum Eval {
-    Numeric(f32),
-    Mate(usize, Colour),
-    Infinity,
-    NegInfinity,
-}
+// A mate on the spot, further mates count down from here one ply at a time
+const MATE: i32 = 31_000;
+// Scores closer to MATE than this are mates, everything below is a heuristic evaluation
+const MAX_MATE_PLY: i32 = 1_000;
+
+/// Evaluation in centipawns from white's point of view. Mates are kept in a range of their own
+/// above and below every heuristic evaluation, the shorter the mate the further out, so that
+/// evaluations are plain integers that compare and order totally.
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Clone, Copy, Default)]
+pub struct Eval(i32);
 impl Eval {
-    pub fn max(self, other: Self) -> Self {
-        if self >= other {
-            self
-        } else {
-            other
-        }
-    }
+    pub const INFINITY: Self = Self(32_000);
+    pub const NEG_INFINITY: Self = Self(-32_000);
+    pub const DRAW: Self = Self(0);
 
-    pub fn min(self, other: Self) -> Self {
-        if self <= other {
-            self
+    /// A heuristic evaluation, kept out of the range of the mate scores.
+    pub const fn centipawns(centipawns: i32) -> Self {
+        let limit = MATE - MAX_MATE_PLY - 1;
+        Self(if centipawns > limit {
+            limit
+        } else if centipawns < -limit {
+            -limit
         } else {
-            other
-        }
+            centipawns
+        })
     }
-}
-impl Add for Eval {
-    type Output = Option<Self>;
-    fn add(self, rhs: Self) -> Self::Output {
-        if let Self::Numeric(n1) = self {
-            if let Self::Numeric(n2) = rhs {
-                return Some(Self::Numeric(n1 + n2));
-            }
+
+    /// Checkmate by `winner`, `ply` half moves from the root of the search.
+    pub fn mate(ply: usize, winner: Colour) -> Self {
+        let score = MATE - (ply as i32).min(MAX_MATE_PLY);
+        match winner {
+            Colour::White => Self(score),
+            Colour::Black => Self(-score),
         }
-        None
     }
-}
-impl Add<f32> for Eval {
-    type Output = Option<Self>;
-    fn add(self, rhs: f32) -> Self::Output {
-        self + Self::Numeric(rhs)
+
+    /// The value in centipawns, mate scores included.
+    pub fn value(self) -> i32 {
+        self.0
     }
-}
 
-impl Sub for Eval {
-    type Output = Option<Self>;
-    fn sub(self, rhs: Self) -> Self::Output {
-        if let Self::Numeric(n1) = self {
-            if let Self::Numeric(n2) = rhs {
-                return Some(Self::Numeric(n1 - n2));
-            }
-        }
-        None
+    /// An evaluation from its value, as given by `value`.
+    pub(crate) fn from_value(value: i32) -> Self {
+        Self(value)
     }
-}
-impl Sub<f32> for Eval {
-    type Output = Option<Self>;
-    fn sub(self, rhs: f32) -> Self::Output {
-        self - Self::Numeric(rhs)
+
+    pub fn is_mate(self) -> bool {
+        (MATE - MAX_MATE_PLY..=MATE).contains(&self.0.abs())
     }
-}
 
-impl PartialOrd for Eval {
-    fn lt(&self, other: &Self) -> bool {
-        !self.ge(other)
+    /// How many half moves from the root the mate is, and who gives it.
+    pub fn mate_ply(self) -> Option<(usize, Colour)> {
+        if !self.is_mate() {
+            return None;
+        }
+        let winner = if self.0 > 0 {
+            Colour::White
+        } else {
+            Colour::Black
+        };
+        Some(((MATE - self.0.abs()) as usize, winner))
     }
-    fn le(&self, other: &Self) -> bool {
-        !self.gt(other)
+
+    /// The number of moves to mate from the root, counting the moves of the side that mates:
+    /// positive when `side` gives the mate and negative when it is mated.
+    pub fn mate_in(self, side: Colour) -> Option<i32> {
+        self.mate_ply().map(|(ply, winner)| {
+            let moves = (ply as i32 + 1) / 2;
+            if winner == side {
+                moves
+            } else {
+                -moves
+            }
+        })
     }
-    fn gt(&self, other: &Self) -> bool {
-        match self {
-            Self::Numeric(val_self) => match other {
-                Self::Numeric(val_other) => val_self > val_other,
-                Self::Mate(_, c) => match c {
-                    Colour::White => false,
-                    Colour::Black => true,
-                },
-                Self::Infinity => false,
-                Self::NegInfinity => true,
-            },
-            Self::Mate(mate_self, c1) => match other {
-                Self::Numeric(_) => match c1 {
-                    Colour::White => true,
-                    Colour::Black => false,
-                },
-                Self::Mate(mate_other, c2) => match c1 {
-                    Colour::White => match c2 {
-                        Colour::White => mate_self < mate_other,
-                        Colour::Black => true,
-                    },
-                    Colour::Black => match c2 {
-                        Colour::White => false,
-                        Colour::Black => mate_self > mate_other,
-                    },
-                },
-                Self::Infinity => false,
-                Self::NegInfinity => true,
-            },
-            Self::Infinity => true,
-            Self::NegInfinity => false,
+
+    /// The evaluation as stored in the transposition table for a node `ply` half moves from the
+    /// root. Mates are counted from the node instead of the root, so the entry holds for the
+    /// position wherever in the tree it is found again.
+    pub fn node_relative(self, ply: usize) -> Self {
+        match self.0 {
+            n if self.is_mate() && n > 0 => Self(n + ply as i32),
+            n if self.is_mate() => Self(n - ply as i32),
+            _ => self,
         }
     }
-    fn ge(&self, other: &Self) -> bool {
-        match self {
-            Self::Numeric(val_self) => match other {
-                Self::Numeric(val_other) => val_self >= val_other,
-                Self::Mate(_, c) => match c {
-                    Colour::White => false,
-                    Colour::Black => true,
-                },
-                Self::Infinity => false,
-                Self::NegInfinity => true,
-            },
-            Self::Mate(mate_self, c1) => match other {
-                Self::Numeric(_) => match c1 {
-                    Colour::White => true,
-                    Colour::Black => false,
-                },
-                Self::Mate(mate_other, c2) => match c1 {
-                    Colour::White => match c2 {
-                        Colour::White => mate_self <= mate_other,
-                        Colour::Black => true,
-                    },
-                    Colour::Black => match c2 {
-                        Colour::White => false,
-                        Colour::Black => mate_self >= mate_other,
-                    },
-                },
-                Self::Infinity => false,
-                Self::NegInfinity => true,
-            },
-            Self::Infinity => true,
-            Self::NegInfinity => false,
+
+    /// The evaluation of a transposition table entry found `ply` half moves from the root.
+    pub fn root_relative(self, ply: usize) -> Self {
+        match self.0 {
+            n if self.is_mate() && n > 0 => Self(n - ply as i32),
+            n if self.is_mate() => Self(n + ply as i32),
+            _ => self,
         }
     }
-    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
-        if self > other {
-            Some(Ordering::Greater)
-        } else if self < other {
-            Some(Ordering::Less)
-        } else if self == other {
-            Some(Ordering::Equal)
-        } else {
-            None
-        }
+}
+impl Neg for Eval {
+    type Output = Self;
+    fn neg(self) -> Self::Output {
+        Self(-self.0)
     }
 }
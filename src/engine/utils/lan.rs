@@ -0,0 +1,162 @@
+use std::{fmt::Display, str::FromStr};
+
+use chess_backend::{Board, Colour, Pieces};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Promotion {
+    Knight,
+    Bishop,
+    Rook,
+    Queen,
+}
+impl Promotion {
+    fn to_char(self) -> char {
+        match self {
+            Self::Knight => 'n',
+            Self::Bishop => 'b',
+            Self::Rook => 'r',
+            Self::Queen => 'q',
+        }
+    }
+
+    fn from_char(c: char) -> Option<Self> {
+        match c {
+            'n' => Some(Self::Knight),
+            'b' => Some(Self::Bishop),
+            'r' => Some(Self::Rook),
+            'q' => Some(Self::Queen),
+            _ => None,
+        }
+    }
+}
+
+/// A move in long algebraic notation (`e2e4`, `e7e8q`), as used by the UCI protocol.
+/// Castling is written as the king's move (`e1g1`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct LanMove {
+    pub origin: i32,
+    pub destination: i32,
+    pub promotion: Option<Promotion>,
+}
+impl LanMove {
+    /// Recovers the move that leads from `before` to `after` by comparing the pieces of the side
+    /// that moved. Returns None if the boards are not one move apart.
+    pub fn from_boards(before: &Board, after: &Board) -> Option<Self> {
+        let (pieces_before, pieces_after) = match before.side_to_move() {
+            Colour::White => (
+                Pieces::from(before.base.white),
+                Pieces::from(after.base.white),
+            ),
+            Colour::Black => (
+                Pieces::from(before.base.black),
+                Pieces::from(after.base.black),
+            ),
+        };
+
+        // Castling moves the rook as well, but the king's move is what identifies it
+        if pieces_before.king != pieces_after.king {
+            return Some(Self {
+                origin: *pieces_before.king.first()?,
+                destination: *pieces_after.king.first()?,
+                promotion: None,
+            });
+        }
+
+        let kinds_before = kinds(&pieces_before);
+        let kinds_after = kinds(&pieces_after);
+        let mut vacated = None;
+        let mut occupied = None;
+        for ((squares_before, promotion), (squares_after, _)) in
+            kinds_before.iter().zip(kinds_after.iter())
+        {
+            if let Some(sq) = squares_before.iter().find(|sq| !squares_after.contains(sq)) {
+                vacated = Some((*sq, *promotion));
+            }
+            if let Some(sq) = squares_after.iter().find(|sq| !squares_before.contains(sq)) {
+                occupied = Some((*sq, *promotion));
+            }
+        }
+
+        let (origin, from_kind) = vacated?;
+        let (destination, to_kind) = occupied?;
+        Some(Self {
+            origin,
+            destination,
+            // Only a pawn can turn into another piece
+            promotion: if from_kind.is_none() { to_kind } else { None },
+        })
+    }
+
+    /// Finds the legal move on `board` matching this move and returns the resulting board.
+    pub fn apply(&self, board: &Board) -> Option<Board> {
+        board
+            .generate_legal_moves()
+            .iter()
+            .find(|m| Self::from_boards(board, &m.board) == Some(*self))
+            .map(|m| m.board)
+    }
+}
+
+// Pieces of every kind except the king, paired with the promotion creating them
+fn kinds(pieces: &Pieces) -> [(&[i32], Option<Promotion>); 5] {
+    [
+        (pieces.pawns.as_slice(), None),
+        (pieces.knights.as_slice(), Some(Promotion::Knight)),
+        (pieces.bishops.as_slice(), Some(Promotion::Bishop)),
+        (pieces.rooks.as_slice(), Some(Promotion::Rook)),
+        (pieces.queens.as_slice(), Some(Promotion::Queen)),
+    ]
+}
+
+pub fn square_name(sq: i32) -> String {
+    format!("{}{}", (b'a' + (sq % 8) as u8) as char, sq / 8 + 1)
+}
+
+pub fn parse_square(name: &str) -> Option<i32> {
+    let mut chars = name.chars();
+    let file = chars.next()?;
+    let rank = chars.next()?;
+    if chars.next().is_some() || !('a'..='h').contains(&file) || !('1'..='8').contains(&rank) {
+        return None;
+    }
+    Some((rank as i32 - '1' as i32) * 8 + (file as i32 - 'a' as i32))
+}
+
+impl Display for LanMove {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}{}",
+            square_name(self.origin),
+            square_name(self.destination)
+        )?;
+        if let Some(promotion) = self.promotion {
+            write!(f, "{}", promotion.to_char())?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for LanMove {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if !s.is_ascii() || !(4..=5).contains(&s.len()) {
+            return Err(format!("Invalid move notation '{s}'"));
+        }
+        let origin = parse_square(&s[0..2]).ok_or(format!("Invalid origin square in '{s}'"))?;
+        let destination =
+            parse_square(&s[2..4]).ok_or(format!("Invalid destination square in '{s}'"))?;
+        let promotion = match s[4..].chars().next() {
+            Some(c) => Some(
+                Promotion::from_char(c.to_ascii_lowercase())
+                    .ok_or(format!("Invalid promotion piece in '{s}'"))?,
+            ),
+            None => None,
+        };
+        Ok(Self {
+            origin,
+            destination,
+            promotion,
+        })
+    }
+}
@@ -1,2 +1,4 @@
+pub mod bitboard;
 pub mod eval;
+pub mod lan;
 pub mod phase;
@@ -1,31 +1,49 @@
 use chess_backend::{Board, Pieces};
-use sqlite::Connection;
+
+use crate::engine::zobrist::castling_rights;
+
+/// Material phase of the starting position, see `material_phase`.
+pub const MAX_MATERIAL_PHASE: i32 = 24;
+/// Plies after which the game is out of the opening, however it went.
+pub const OPENING_MAX_PLIES: usize = 24;
+// The opening is over once fewer knights, bishops, rooks and queens than this are left, out of 14
+const OPENING_MIN_PIECES: usize = 12;
+// Starting squares of the knights and bishops
+const WHITE_MINOR_SQUARES: [i32; 4] = [1, 2, 5, 6];
+const BLACK_MINOR_SQUARES: [i32; 4] = [57, 58, 61, 62];
 
 #[derive(Clone, Copy, Debug)]
 pub enum GamePhase {
-    Opening(i64),
+    /// The id of the opening book entry for the position while the book is followed, None once
+    /// the game has left the book but not yet the opening
+    Opening(Option<i64>),
     MiddleGame,
     EndGame,
 }
 impl GamePhase {
     pub fn determine_phase(
         current_phase: Option<GamePhase>,
+        board: &Board,
         white_pieces: &Pieces,
         black_pieces: &Pieces,
     ) -> Self {
         if let Some(phase) = current_phase {
             match phase {
                 GamePhase::Opening(id) => {
-                    unimplemented!()
-                    // Check if opening database can still be used
+                    if opening_over(board, white_pieces, black_pieces) {
+                        Self::determine_middle_or_end(white_pieces, black_pieces)
+                    } else {
+                        GamePhase::Opening(id)
+                    }
                 }
                 GamePhase::MiddleGame => Self::determine_middle_or_end(white_pieces, black_pieces),
                 GamePhase::EndGame => GamePhase::EndGame,
             }
-        } else {
-            // Start by trying to find the position in the opening database. If it cannot be found,
-            // determine middle vs endgame (Same as in middlegame)
+        } else if opening_over(board, white_pieces, black_pieces) {
             Self::determine_middle_or_end(white_pieces, black_pieces)
+        } else {
+            // Positions the book knows have been given their phase already
+            GamePhase::Opening(None)
         }
     }
 
@@ -38,6 +56,48 @@ impl GamePhase {
     }
 }
 
+// The opening ends once pieces have been exchanged, or once both sides have brought out their
+// knights and bishops and castled or given up castling. How many moves have been played is not
+// part of the board, so `EngineController` ends the opening after `OPENING_MAX_PLIES` itself.
+fn opening_over(board: &Board, white_pieces: &Pieces, black_pieces: &Pieces) -> bool {
+    non_pawn_count(white_pieces) + non_pawn_count(black_pieces) < OPENING_MIN_PIECES
+        || (developed(white_pieces, &WHITE_MINOR_SQUARES)
+            && developed(black_pieces, &BLACK_MINOR_SQUARES)
+            && !castling_rights(board).contains(&true))
+}
+
+/// How much of the non-pawn material is left, from 0 with none to `MAX_MATERIAL_PHASE` with all
+/// of it. Knights and bishops count 1, rooks 2 and queens 4. Unlike `GamePhase`, it changes a
+/// little with every exchange.
+pub fn material_phase(white_pieces: &Pieces, black_pieces: &Pieces) -> i32 {
+    let phase = |pieces: &Pieces| {
+        pieces.knights.len()
+            + pieces.bishops.len()
+            + 2 * pieces.rooks.len()
+            + 4 * pieces.queens.len()
+    };
+    // Promotions can take it above the start
+    ((phase(white_pieces) + phase(black_pieces)) as i32).min(MAX_MATERIAL_PHASE)
+}
+
+/// Blends a middlegame and an endgame value in centipawns by the material phase.
+pub fn taper_centipawns(middlegame: i32, endgame: i32, material_phase: i32) -> i32 {
+    (middlegame * material_phase + endgame * (MAX_MATERIAL_PHASE - material_phase))
+        / MAX_MATERIAL_PHASE
+}
+
+fn developed(pieces: &Pieces, minor_squares: &[i32]) -> bool {
+    !pieces
+        .knights
+        .iter()
+        .chain(&pieces.bishops)
+        .any(|sq| minor_squares.contains(sq))
+}
+
+fn non_pawn_count(pieces: &Pieces) -> usize {
+    pieces.knights.len() + pieces.bishops.len() + pieces.rooks.len() + pieces.queens.len()
+}
+
 macro_rules! gen_piece_count {
     ($($param:ident),*) => {
         fn piece_count(pieces: &Pieces) -> usize {
@@ -0,0 +1,109 @@
+use std::sync::OnceLock;
+
+use chess_backend::{Board, Colour, Pieces};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+// The keys are generated from a fixed seed so that hashes are the same on every run
+const SEED: u64 = 0x2545_f491_4f6c_dd1d;
+
+struct ZobristKeys {
+    // Indexed by colour, piece kind (same order as the piece-square tables) and square
+    pieces: [[[u64; 64]; 6]; 2],
+    black_to_move: u64,
+    castling: [u64; 4],
+    en_passant: [u64; 8],
+}
+impl ZobristKeys {
+    fn generate() -> Self {
+        let mut rng = StdRng::seed_from_u64(SEED);
+        let mut keys = Self {
+            pieces: [[[0; 64]; 6]; 2],
+            black_to_move: rng.gen(),
+            castling: rng.gen(),
+            en_passant: rng.gen(),
+        };
+        for colour in &mut keys.pieces {
+            for kind in colour.iter_mut() {
+                rng.fill(&mut kind[..]);
+            }
+        }
+        keys
+    }
+}
+
+fn keys() -> &'static ZobristKeys {
+    static KEYS: OnceLock<ZobristKeys> = OnceLock::new();
+    KEYS.get_or_init(ZobristKeys::generate)
+}
+
+/// Zobrist hash of a position, covering piece placement, side to move, castling rights and the
+/// en passant file.
+pub fn hash(board: &Board) -> u64 {
+    let keys = keys();
+    let mut hash = 0;
+
+    let white_pieces = Pieces::from(board.base.white);
+    let black_pieces = Pieces::from(board.base.black);
+    for (colour, pieces) in [&white_pieces, &black_pieces].iter().enumerate() {
+        for (kind, squares) in piece_lists(pieces).iter().enumerate() {
+            for sq in squares.iter() {
+                hash ^= keys.pieces[colour][kind][*sq as usize];
+            }
+        }
+    }
+
+    if board.side_to_move() == Colour::Black {
+        hash ^= keys.black_to_move;
+    }
+    for (right, key) in castling_rights(board).iter().zip(keys.castling) {
+        if *right {
+            hash ^= key;
+        }
+    }
+    if let Some(sq) = en_passant_square(board) {
+        hash ^= keys.en_passant[(sq % 8) as usize];
+    }
+
+    hash
+}
+
+/// Zobrist hash of the pawns alone, for tables of pawn structure evaluations.
+pub fn pawn_hash(white_pawns: &[i32], black_pawns: &[i32]) -> u64 {
+    let keys = keys();
+    let mut hash = 0;
+    for (colour, pawns) in [white_pawns, black_pawns].iter().enumerate() {
+        for sq in pawns.iter() {
+            hash ^= keys.pieces[colour][0][*sq as usize];
+        }
+    }
+    hash
+}
+
+/// Castling rights in the order white kingside, white queenside, black kingside, black
+/// queenside.
+pub fn castling_rights(board: &Board) -> [bool; 4] {
+    let rights = board.base.castling;
+    [
+        rights & 0b0001 != 0,
+        rights & 0b0010 != 0,
+        rights & 0b0100 != 0,
+        rights & 0b1000 != 0,
+    ]
+}
+
+/// The square a pawn can be captured on en passant, if the last move was a double pawn push.
+pub fn en_passant_square(board: &Board) -> Option<i32> {
+    let sq = board.base.en_passant;
+    (0..64).contains(&sq).then_some(sq)
+}
+
+pub fn piece_lists(pieces: &Pieces) -> [&[i32]; 6] {
+    [
+        &pieces.pawns,
+        &pieces.knights,
+        &pieces.bishops,
+        &pieces.rooks,
+        &pieces.queens,
+        &pieces.king,
+    ]
+}
@@ -1,41 +1,57 @@
 mod tests;
 
-use std::{thread, time::Duration};
-
+use std::{
+    env,
+    io::{self, BufRead},
+    iter, process,
+};
+
+mod build_book;
+mod cecp;
 mod engine;
-use chess_backend::{Board, START_POSITION};
+mod uci;
 use engine::EngineController;
 
-fn main() {
-    book_move_testing();
-
-    //engine_play();
-}
+const ENGINE_NAME: &str = "chess_engine";
 
-fn book_move_testing() {
-    EngineController::init();
-
-    let mut controller = EngineController::default();
-    controller.pick_move(Duration::from_secs(1));
-    controller.show_board();
-}
-
-fn engine_play() {
+fn main() {
     EngineController::init();
 
-    let mut controller = EngineController::new(
-        Board::from("rn2kb1r/pp3bpp/4p3/5pBq/3P4/3B2Q1/PPP2PPP/R3K2R w KQkq - 0 1"),
-        2,
-    );
-
-    controller.show_board();
-    loop {
-        if controller.is_over() {
-            break;
-        } else {
-            controller.pick_move(Duration::from_secs(1));
-            controller.show_board();
+    let mut args = env::args().skip(1).peekable();
+    if args.next_if_eq("build-book").is_some() {
+        if let Err(e) = build_book::run(args) {
+            eprintln!("{e}");
+            process::exit(1);
         }
+        return;
+    }
+
+    // GUIs start engines from directories of their own, so the book can be given explicitly
+    let mut builder = EngineController::builder();
+    while let Some(arg) = args.next() {
+        builder = match (arg.as_str(), args.next_if(|path| !path.starts_with("--"))) {
+            ("--book", Some(path)) => builder.book(path),
+            ("--no-book", None) => builder.without_book(),
+            ("--threads", Some(n)) if n.parse::<usize>().is_ok() => {
+                // Unwrap is safe, the guard parsed it already
+                builder.workers(n.parse().unwrap())
+            }
+            _ => {
+                eprintln!("Usage: {ENGINE_NAME} [--book FILE | --no-book] [--threads N]");
+                process::exit(1);
+            }
+        };
+    }
+    let controller = builder.build().unwrap_or_else(|e| {
+        eprintln!("Failed to open the opening book: {e}");
+        process::exit(1);
+    });
+
+    // The first command tells which protocol the GUI speaks
+    let mut input = io::stdin().lock().lines().map_while(Result::ok);
+    match input.next() {
+        Some(first) if first.trim() == "xboard" => cecp::run(input, controller),
+        Some(first) => uci::run(iter::once(first).chain(input), controller),
+        None => (),
     }
-    println!("Game ended with {:?}", controller.get_game_state());
 }
@@ -1,7 +1,3 @@
-use std::time::SystemTime;
-
-use chess_backend::{init, Board, START_POSITION};
-
 #[test]
 fn bench_eval() {
     // init();
@@ -63,60 +63,1222 @@ mod eval {
     use chess_backend::Colour;
 
     #[test]
-    fn test_paritalord() {
+    fn ordering() {
         // Checkmate eval for white
-        assert!(Eval::Mate(1, Colour::White) > Eval::Mate(3, Colour::White));
-        assert!(Eval::Mate(2, Colour::White) >= Eval::Mate(2, Colour::White));
-        assert!(Eval::Mate(1, Colour::White) >= Eval::Mate(3, Colour::White));
-
-        assert!(Eval::Mate(6, Colour::White) < Eval::Mate(3, Colour::White));
-        assert!(Eval::Mate(5, Colour::White) <= Eval::Mate(5, Colour::White));
-        assert!(Eval::Mate(6, Colour::White) <= Eval::Mate(3, Colour::White));
+        assert!(Eval::mate(1, Colour::White) > Eval::mate(3, Colour::White));
+        assert!(Eval::mate(2, Colour::White) >= Eval::mate(2, Colour::White));
+        assert!(Eval::mate(6, Colour::White) < Eval::mate(3, Colour::White));
 
         // Checkmate eval for black
-        assert!(Eval::Mate(1, Colour::Black) < Eval::Mate(3, Colour::Black));
-        assert!(Eval::Mate(2, Colour::Black) <= Eval::Mate(2, Colour::Black));
-        assert!(Eval::Mate(1, Colour::Black) <= Eval::Mate(3, Colour::Black));
-
-        assert!(Eval::Mate(6, Colour::Black) > Eval::Mate(3, Colour::Black));
-        assert!(Eval::Mate(5, Colour::Black) >= Eval::Mate(5, Colour::Black));
-        assert!(Eval::Mate(6, Colour::Black) >= Eval::Mate(3, Colour::Black));
+        assert!(Eval::mate(1, Colour::Black) < Eval::mate(3, Colour::Black));
+        assert!(Eval::mate(2, Colour::Black) <= Eval::mate(2, Colour::Black));
+        assert!(Eval::mate(6, Colour::Black) > Eval::mate(3, Colour::Black));
 
         // Checkmate white vs black
-        assert!(Eval::Mate(5, Colour::White) > Eval::Mate(2, Colour::Black));
+        assert!(Eval::mate(5, Colour::White) > Eval::mate(2, Colour::Black));
 
         // Numerics
-        assert!(Eval::Numeric(7.) > Eval::Numeric(4.));
-        assert!(Eval::Numeric(4.) >= Eval::Numeric(4.));
-        assert!(Eval::Numeric(7.) >= Eval::Numeric(4.));
-
-        assert!(Eval::Numeric(4.) < Eval::Numeric(7.));
-        assert!(Eval::Numeric(4.) <= Eval::Numeric(4.));
-        assert!(Eval::Numeric(4.) <= Eval::Numeric(7.));
-
-        // Numerics vs checkmate
-        assert!(Eval::Mate(9, Colour::White) > Eval::Numeric(100.));
-        assert!(Eval::Numeric(100.) < Eval::Mate(9, Colour::White));
-        assert!(Eval::Mate(1, Colour::Black) < Eval::Numeric(-100.));
-        assert!(Eval::Numeric(-100.) > Eval::Mate(1, Colour::Black));
-
-        // Numerics vs infinity
-        assert!(Eval::Numeric(0.) < Eval::Infinity);
-        assert!(Eval::Infinity > Eval::Numeric(0.));
-        assert!(Eval::Numeric(-1.) > Eval::NegInfinity);
-        assert!(Eval::NegInfinity < Eval::Numeric(-1.));
-
-        // checkmate vs infinity
-        assert!(Eval::Mate(1, Colour::White) < Eval::Infinity);
-        assert!(Eval::Infinity > Eval::Mate(1, Colour::White));
-        assert!(Eval::Mate(1, Colour::Black) > Eval::NegInfinity);
-        assert!(Eval::NegInfinity < Eval::Mate(1, Colour::Black));
+        assert!(Eval::centipawns(700) > Eval::centipawns(400));
+        assert!(Eval::centipawns(400) >= Eval::centipawns(400));
+        assert!(Eval::centipawns(400) < Eval::centipawns(700));
+
+        // Numerics vs checkmate, however large the numeric evaluation
+        assert!(Eval::mate(9, Colour::White) > Eval::centipawns(10_000));
+        assert!(Eval::centipawns(i32::MAX) < Eval::mate(100, Colour::White));
+        assert!(Eval::mate(1, Colour::Black) < Eval::centipawns(-10_000));
+        assert!(Eval::centipawns(i32::MIN) > Eval::mate(100, Colour::Black));
+        assert!(!Eval::centipawns(i32::MAX).is_mate());
+
+        // Numerics and checkmate vs infinity
+        assert!(Eval::centipawns(0) < Eval::INFINITY);
+        assert!(Eval::centipawns(-1) > Eval::NEG_INFINITY);
+        assert!(Eval::mate(0, Colour::White) < Eval::INFINITY);
+        assert!(Eval::mate(0, Colour::Black) > Eval::NEG_INFINITY);
 
         // maximizing
         assert_eq!(
-            Eval::Mate(1, Colour::White).max(Eval::Infinity),
-            Eval::Infinity
+            Eval::mate(1, Colour::White).max(Eval::INFINITY),
+            Eval::INFINITY
+        );
+    }
+
+    #[test]
+    fn mate_distance() {
+        let mate = Eval::mate(7, Colour::Black);
+        assert_eq!(mate.mate_ply(), Some((7, Colour::Black)));
+        assert_eq!(Eval::centipawns(300).mate_ply(), None);
+        assert_eq!(-Eval::mate(7, Colour::Black), Eval::mate(7, Colour::White));
+
+        // Stored in the table at ply 3, the mate is 4 plies away from the node
+        let stored = mate.node_relative(3);
+        assert_eq!(stored, Eval::mate(4, Colour::Black));
+        assert_eq!(stored.root_relative(3), mate);
+        // Found again at ply 5 by another path it is 9 plies from the root
+        assert_eq!(stored.root_relative(5), Eval::mate(9, Colour::Black));
+        assert_eq!(
+            Eval::centipawns(-40).node_relative(3),
+            Eval::centipawns(-40)
+        );
+
+        // Counted in moves of the side that mates
+        assert_eq!(Eval::mate(1, Colour::White).mate_in(Colour::White), Some(1));
+        assert_eq!(Eval::mate(3, Colour::White).mate_in(Colour::White), Some(2));
+        assert_eq!(
+            Eval::mate(4, Colour::White).mate_in(Colour::Black),
+            Some(-2)
+        );
+        assert_eq!(Eval::centipawns(500).mate_in(Colour::White), None);
+    }
+}
+
+#[cfg(test)]
+mod lan {
+    use crate::engine::utils::lan::{LanMove, Promotion};
+
+    #[test]
+    fn parse_and_display() {
+        let mv: LanMove = "e2e4".parse().unwrap();
+        assert_eq!(mv.origin, 12);
+        assert_eq!(mv.destination, 28);
+        assert_eq!(mv.promotion, None);
+        assert_eq!(mv.to_string(), "e2e4");
+
+        let promotion: LanMove = "a7a8q".parse().unwrap();
+        assert_eq!(promotion.origin, 48);
+        assert_eq!(promotion.destination, 56);
+        assert_eq!(promotion.promotion, Some(Promotion::Queen));
+        assert_eq!(promotion.to_string(), "a7a8q");
+
+        assert!("e2e9".parse::<LanMove>().is_err());
+        assert!("e7e8k".parse::<LanMove>().is_err());
+        assert!("e2".parse::<LanMove>().is_err());
+    }
+}
+
+#[cfg(test)]
+mod transposition {
+    use crate::engine::{
+        transposition::{Bound, TranspositionTable, TtEntry},
+        utils::{
+            eval::Eval,
+            lan::{LanMove, Promotion},
+        },
+    };
+    use chess_backend::Colour;
+
+    #[test]
+    fn store_and_probe() {
+        let tt = TranspositionTable::new(1);
+        let entry = TtEntry {
+            depth: 7,
+            bound: Bound::Lower,
+            best_move: Some(LanMove {
+                origin: 52,
+                destination: 60,
+                promotion: Some(Promotion::Knight),
+            }),
+            eval: Eval::centipawns(-125),
+        };
+        tt.store(0xdead_beef, entry);
+        assert_eq!(tt.probe(0xdead_beef), Some(entry));
+        assert_eq!(tt.probe(0xdead_beee), None);
+
+        let mate = TtEntry {
+            depth: 3,
+            bound: Bound::Exact,
+            best_move: None,
+            eval: Eval::mate(5, Colour::Black),
+        };
+        tt.store(42, mate);
+        assert_eq!(tt.probe(42), Some(mate));
+
+        // A shallower search does not replace a deeper one of the same position
+        tt.store(42, TtEntry { depth: 1, ..mate });
+        assert_eq!(tt.probe(42), Some(mate));
+
+        tt.clear();
+        assert_eq!(tt.probe(42), None);
+    }
+}
+
+#[cfg(test)]
+mod tree {
+    use std::{mem, sync::atomic::AtomicBool};
+
+    use crate::engine::{
+        move_ordering::OrderingTables,
+        pv::PrincipalVariation,
+        transposition::TranspositionTable,
+        tree::{Node, Tree},
+        zobrist,
+    };
+    use chess_backend::{init, Board, Colour};
+
+    #[test]
+    fn expand_and_prune() {
+        init();
+        let mut tree = Tree::new(Board::default(), None, 1024 * 1024);
+        let children = tree.node(Tree::ROOT).generate_children();
+        assert!(tree.expand(Tree::ROOT, children));
+        assert_eq!(tree.node_count(), 21);
+
+        let first = tree.children(Tree::ROOT).next().unwrap();
+        let grandchildren = tree.node(first).generate_children();
+        assert!(tree.expand(first, grandchildren));
+        assert_eq!(tree.node_count(), 41);
+
+        // Pruning the root keeps only the chosen child, which keeps its own subtree
+        tree.prune_children(Tree::ROOT, Some(first));
+        assert_eq!(tree.node_count(), 22);
+        assert_eq!(tree.children(Tree::ROOT).collect::<Vec<_>>(), vec![first]);
+
+        // Freed nodes are reused instead of growing the arena
+        let mut usage = None;
+        for _ in 0..3 {
+            let children = tree.node(first).generate_children();
+            assert!(tree.expand(first, children));
+            assert_eq!(tree.node_count(), 22);
+            assert_eq!(
+                *usage.get_or_insert(tree.memory_usage()),
+                tree.memory_usage()
+            );
+        }
+    }
+
+    #[test]
+    fn memory_limit() {
+        init();
+        let mut tree = Tree::new(Board::default(), None, 10 * mem::size_of::<Node>());
+        let children = tree.node(Tree::ROOT).generate_children();
+        assert!(!tree.expand(Tree::ROOT, children));
+        assert_eq!(tree.node_count(), 1);
+
+        // A node resolved like a leaf for lack of room is not stored as a deep search
+        let tt = TranspositionTable::new(1);
+        tree.run_root(
+            3,
+            true,
+            &mut OrderingTables::default(),
+            &tt,
+            &AtomicBool::new(false),
+        );
+        let entry = tt.probe(zobrist::hash(&Board::default())).unwrap();
+        assert_eq!(entry.depth, 0);
+    }
+
+    #[test]
+    fn mate_distance() {
+        init();
+        let board = Board::from("6k1/5ppp/8/8/8/8/8/R5K1 w - - 0 1");
+        let mut tree = Tree::new(board, None, 64 * 1024 * 1024);
+        // Searching deeper still finds the shortest mate
+        let eval = tree.run_root(
+            4,
+            true,
+            &mut OrderingTables::default(),
+            &TranspositionTable::new(1),
+            &AtomicBool::new(false),
+        );
+        assert_eq!(eval.and_then(|eval| eval.mate_in(Colour::White)), Some(1));
+
+        // The line ends with the mate
+        let pv = PrincipalVariation::from_line(&board, &tree.principal_variation(true));
+        assert_eq!(pv.uci(), "a1a8");
+        assert_eq!(pv.san, vec!["Ra8#"]);
+    }
+}
+
+#[cfg(test)]
+mod pv {
+    use std::time::Duration;
+
+    use crate::engine::pv::PrincipalVariation;
+    use chess_backend::{init, Board};
+
+    #[test]
+    fn line() {
+        init();
+        let start = Board::default();
+        let e4 = start
+            .generate_legal_moves()
+            .iter()
+            .map(|m| m.board)
+            .find(|board| start.get_san(board) == "e4")
+            .unwrap();
+        let e5 = e4
+            .generate_legal_moves()
+            .iter()
+            .map(|m| m.board)
+            .find(|board| e4.get_san(board) == "e5")
+            .unwrap();
+
+        let pv = PrincipalVariation {
+            nodes: 5000,
+            time: Duration::from_millis(250),
+            ..PrincipalVariation::from_line(&start, &[e4, e5])
+        };
+        assert_eq!(pv.uci(), "e2e4 e7e5");
+        assert_eq!(pv.san, vec!["e4", "e5"]);
+        assert_eq!(pv.nps(), 20_000);
+
+        // A position that does not follow ends the line
+        let pv = PrincipalVariation::from_line(&start, &[e5]);
+        assert!(pv.moves.is_empty());
+        assert_eq!(PrincipalVariation::default().nps(), 0);
+    }
+}
+
+#[cfg(test)]
+mod opening_book {
+    use crate::engine::{
+        opening_book::{BookError, BookPolicy, OpeningBook, BOOK_ROOT},
+        utils::phase::GamePhase,
+    };
+    use chess_backend::{init, Board, Colour};
+    use sqlite::Connection;
+    use std::{env, fs};
+
+    fn test_book() -> OpeningBook {
+        let conn = Connection::open(":memory:").unwrap();
+        conn.execute(
+            "CREATE TABLE moves (id INTEGER PRIMARY KEY, parent_move INTEGER, san TEXT, \
+             eval REAL, frequency INTEGER, terminal INTEGER);
+             INSERT INTO moves VALUES (1, 0, '', 0.0, 100, 0);
+             INSERT INTO moves VALUES (2, 1, 'e4', 0.3, 60, 0);
+             INSERT INTO moves VALUES (3, 1, 'd4', 0.3, 30, 1);
+             INSERT INTO moves VALUES (4, 1, 'Nf3', 0.2, 2, 0);
+             INSERT INTO moves VALUES (5, 2, 'e5', 0.3, 2, 0);",
+        )
+        .unwrap();
+        OpeningBook::new(conn)
+    }
+
+    fn after(board: &Board, san: &str) -> Board {
+        board
+            .generate_legal_moves()
+            .into_iter()
+            .map(|m| m.board)
+            .find(|next| board.get_san(next) == san)
+            .unwrap()
+    }
+
+    #[test]
+    fn plays_most_frequent() {
+        init();
+        let mut book = test_book();
+        let board = Board::default();
+        let (next, phase) = book.find_bookmove(&board, BOOK_ROOT).unwrap();
+        assert_eq!(board.get_san(&next), "e4");
+        assert!(matches!(phase, GamePhase::Opening(Some(2))));
+
+        // Replies that are too rare to trust are not played
+        assert!(book.find_bookmove(&next, 2).is_none());
+    }
+
+    #[test]
+    fn policies() {
+        init();
+        let board = Board::default();
+        let played = |policy, seed| {
+            let mut book = test_book();
+            book.set_policy(policy);
+            book.seed(seed);
+            (0..50)
+                .map(|_| {
+                    let (next, _) = book.find_bookmove(&board, BOOK_ROOT).unwrap();
+                    board.get_san(&next)
+                })
+                .collect::<Vec<_>>()
+        };
+
+        // The same seed gives the same games
+        assert_eq!(
+            played(BookPolicy::FrequencyWeighted, 7),
+            played(BookPolicy::FrequencyWeighted, 7)
+        );
+
+        let weighted = played(BookPolicy::FrequencyWeighted, 7);
+        assert!(weighted.iter().any(|san| san == "e4"));
+        assert!(weighted.iter().any(|san| san == "d4"));
+        assert!(weighted.iter().all(|san| san != "Nf3"));
+
+        // Both moves are evaluated the same, so both are played
+        let by_eval = played(BookPolicy::EvalWeighted, 7);
+        assert!(by_eval.iter().any(|san| san == "e4"));
+        assert!(by_eval.iter().any(|san| san == "d4"));
+
+        let uniform = played(BookPolicy::UniformAbove(1), 7);
+        assert!(uniform.iter().any(|san| san == "Nf3"));
+    }
+
+    #[test]
+    fn follows_moves() {
+        init();
+        let book = test_book();
+        let board = Board::default();
+        assert!(matches!(
+            book.follow_move(&board, BOOK_ROOT, &after(&board, "Nf3")),
+            GamePhase::Opening(Some(4))
+        ));
+        // The book ends after a terminal move, as it does after an unknown one, but not the opening
+        assert!(matches!(
+            book.follow_move(&board, BOOK_ROOT, &after(&board, "d4")),
+            GamePhase::Opening(None)
+        ));
+        assert!(matches!(
+            book.follow_move(&board, BOOK_ROOT, &after(&board, "c4")),
+            GamePhase::Opening(None)
+        ));
+    }
+
+    #[test]
+    fn learns_from_results() {
+        init();
+        let mut book = test_book();
+        let board = Board::default();
+        assert_eq!(
+            book.entry_id(&board, BOOK_ROOT, &after(&board, "e4")),
+            Some(2)
+        );
+        assert_eq!(book.entry_id(&board, BOOK_ROOT, &after(&board, "c4")), None);
+
+        // Losses for white
+        for _ in 0..10 {
+            book.learn(&[(2, Colour::White)], Some(Colour::Black))
+                .unwrap();
+        }
+        let (next, _) = book.find_bookmove(&board, BOOK_ROOT).unwrap();
+        assert_eq!(board.get_san(&next), "d4");
+    }
+
+    #[test]
+    fn bad_books() {
+        init();
+        assert!(matches!(
+            OpeningBook::open("no/such/openings.db"),
+            Err(BookError::Io(_))
+        ));
+
+        let path = env::temp_dir().join("chess_engine_bad_book.db");
+        let _ = fs::remove_file(&path);
+        let conn = Connection::open(&path).unwrap();
+        assert!(matches!(
+            OpeningBook::open(&path),
+            Err(BookError::MissingTable)
+        ));
+        conn.execute("CREATE TABLE moves (id INTEGER PRIMARY KEY, parent_move INTEGER, san TEXT)")
+            .unwrap();
+        assert!(matches!(
+            OpeningBook::open(&path),
+            Err(BookError::MissingColumn("eval"))
+        ));
+        fs::remove_file(&path).unwrap();
+
+        // Without a book every position is searched
+        let mut book = OpeningBook::empty();
+        let board = Board::default();
+        assert!(book.find_bookmove(&board, BOOK_ROOT).is_none());
+        assert!(matches!(
+            book.follow_move(&board, BOOK_ROOT, &after(&board, "e4")),
+            GamePhase::Opening(None)
+        ));
+    }
+
+    #[test]
+    fn transpositions() {
+        init();
+        let conn = Connection::open(":memory:").unwrap();
+        conn.execute(
+            "CREATE TABLE moves (id INTEGER PRIMARY KEY, parent_move INTEGER, san TEXT, \
+             eval REAL, frequency INTEGER, terminal INTEGER);
+             INSERT INTO moves VALUES (1, 0, '', 0.0, 100, 0);
+             INSERT INTO moves VALUES (2, 1, 'd4', 0.3, 60, 0);
+             INSERT INTO moves VALUES (3, 2, 'Nf6', 0.3, 60, 0);
+             INSERT INTO moves VALUES (4, 3, 'c4', 0.3, 60, 0);
+             INSERT INTO moves VALUES (5, 4, 'e6', 0.3, 60, 0);
+             INSERT INTO moves VALUES (6, 1, 'c4', 0.3, 10, 0);
+             INSERT INTO moves VALUES (7, 6, 'Nf6', 0.3, 10, 0);
+             INSERT INTO moves VALUES (8, 7, 'd4', 0.3, 10, 0);",
+        )
+        .unwrap();
+        let mut book = OpeningBook::new(conn);
+
+        let board = ["c4", "Nf6", "d4"]
+            .iter()
+            .fold(Board::default(), |board, san| after(&board, san));
+        // Entry 8 has no moves of its own
+        assert!(book.find_bookmove(&board, 8).is_none());
+        assert!(book.probe(&board).is_none());
+
+        book.set_position_lookup(true).unwrap();
+        assert!(matches!(book.probe(&board), Some(GamePhase::Opening(_))));
+        let (next, _) = book.find_bookmove(&board, 8).unwrap();
+        assert_eq!(board.get_san(&next), "e6");
+    }
+}
+
+#[cfg(test)]
+mod polyglot {
+    use crate::engine::{
+        opening_book::polyglot::{
+            decode_move, encode_move, polyglot_key, PolyglotBook, PolyglotEntry,
+        },
+        utils::lan::LanMove,
+    };
+    use chess_backend::{init, Board};
+
+    fn entry_bytes(key: u64, mv: u16, weight: u16) -> Vec<u8> {
+        [
+            &key.to_be_bytes()[..],
+            &mv.to_be_bytes(),
+            &weight.to_be_bytes(),
+            &0u32.to_be_bytes(),
+        ]
+        .concat()
+    }
+
+    #[test]
+    fn binary_search() {
+        let data = [(1, 10, 5), (3, 11, 1), (3, 12, 2), (3, 13, 3), (7, 14, 4)]
+            .iter()
+            .flat_map(|(key, mv, weight)| entry_bytes(*key, *mv, *weight))
+            .collect();
+        let book = PolyglotBook::from_bytes(data).unwrap();
+        assert_eq!(book.len(), 5);
+
+        let entries = book.entries(3);
+        assert_eq!(entries.len(), 3);
+        assert!(entries.iter().all(|e| e.key == 3));
+        assert_eq!(
+            entries.iter().map(|e| e.mv).collect::<Vec<_>>(),
+            vec![11, 12, 13]
+        );
+        assert_eq!(
+            book.entries(7),
+            vec![PolyglotEntry {
+                key: 7,
+                mv: 14,
+                weight: 4,
+                learn: 0
+            }]
+        );
+        assert!(book.entries(2).is_empty());
+        assert!(book.entries(8).is_empty());
+
+        assert!(PolyglotBook::from_bytes(vec![0; 17]).is_err());
+        assert!(!book.is_empty());
+        assert!(PolyglotBook::from_bytes(Vec::new()).unwrap().is_empty());
+    }
+
+    #[test]
+    fn keys() {
+        init();
+        // Reference keys from the Polyglot specification
+        assert_eq!(polyglot_key(&Board::default()), 0x463b_9618_1691_fc9c);
+        assert_eq!(
+            polyglot_key(&Board::from(
+                "rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq e3 0 1"
+            )),
+            0x823c_9b50_fd11_4196
+        );
+        assert_eq!(
+            polyglot_key(&Board::from(
+                "rnbqkbnr/ppp1p1pp/8/3pPp2/8/8/PPPP1PPP/RNBQKBNR w KQkq f6 0 3"
+            )),
+            0x22a4_8b5a_8e47_ff78
+        );
+    }
+
+    #[test]
+    fn castling_moves() {
+        init();
+        let board = Board::from("r3k2r/8/8/8/8/8/8/R3K2R w KQkq - 0 1");
+        // e1h1 and e1a1, the king taking its own rook
+        assert_eq!(
+            decode_move(0x107, &board),
+            "e1g1".parse::<LanMove>().unwrap()
+        );
+        assert_eq!(
+            decode_move(0x100, &board),
+            "e1c1".parse::<LanMove>().unwrap()
+        );
+        // A rook move along the same squares is left alone
+        assert_eq!(
+            decode_move(0x1ff, &board),
+            "h1h8".parse::<LanMove>().unwrap()
+        );
+
+        for mv in [0x107, 0x100, 0x1ff] {
+            assert_eq!(encode_move(decode_move(mv, &board), &board), mv);
+        }
+    }
+}
+
+#[cfg(test)]
+mod book_builder {
+    use crate::engine::opening_book::{builder::BookBuilder, polyglot::polyglot_key};
+    use chess_backend::{init, Board};
+    use sqlite::{Connection, State};
+
+    const GAMES: &str = r#"[Event "One"]
+[Result "1-0"]
+
+1. e4 {best by test} e5 2. Nf3 (2. f4 exf4) Nc6 $1 1-0
+
+[Event "Two"]
+[Result "1/2-1/2"]
+
+1.e4 e5 2.Nf3 Nf6 ; a comment
+1/2-1/2
+
+[Event "Three"]
+
+1. d4 d5 0-1
+"#;
+
+    // Frequency, wins, draws, losses and terminal of the move reached by `san`
+    fn row(conn: &Connection, san: &str) -> Option<[i64; 5]> {
+        let mut stm = conn
+            .prepare("SELECT * FROM moves WHERE san = :san")
+            .unwrap();
+        stm.bind((":san", san)).unwrap();
+        match stm.next().unwrap() {
+            State::Row => Some(
+                ["frequency", "wins", "draws", "losses", "terminal"]
+                    .map(|column| stm.read::<i64, _>(column).unwrap()),
+            ),
+            State::Done => None,
+        }
+    }
+
+    #[test]
+    fn builds_from_pgn() {
+        init();
+        let mut builder = BookBuilder::new(4, 2);
+        assert_eq!(builder.add_pgn(GAMES), 3);
+
+        let conn = Connection::open(":memory:").unwrap();
+        // The starting position and the moves of the first three plies played twice
+        assert_eq!(builder.write_sqlite(&conn).unwrap(), 4);
+        assert_eq!(row(&conn, "e4"), Some([2, 1, 1, 0, 0]));
+        assert_eq!(row(&conn, "e5"), Some([2, 0, 1, 1, 0]));
+        assert_eq!(row(&conn, "Nf3"), Some([2, 1, 1, 0, 1]));
+        assert_eq!(row(&conn, "d4"), None);
+        assert_eq!(row(&conn, "f4"), None);
+
+        let book = builder.polyglot();
+        let entries = book.entries(polyglot_key(&Board::default()));
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].weight, 3);
+
+        // Books are extended rather than replaced
+        let mut builder = BookBuilder::new(4, 2);
+        builder.load_sqlite(&conn).unwrap();
+        builder.add_pgn(GAMES);
+        builder.write_sqlite(&conn).unwrap();
+        assert_eq!(row(&conn, "e4"), Some([4, 2, 2, 0, 0]));
+        assert_eq!(row(&conn, "Nc6"), None);
+        assert_eq!(row(&conn, ""), Some([6, 0, 0, 0, 0]));
+    }
+
+    #[test]
+    fn castling_with_zeros() {
+        init();
+        let mut builder = BookBuilder::new(8, 1);
+        let game = "1. e4 e5 2. Nf3 Nf6 3. Bc4 Bc5 4. 0-0 0-0 1-0";
+        assert_eq!(builder.add_pgn(game), 1);
+
+        let conn = Connection::open(":memory:").unwrap();
+        // Both castling moves are read, not cut short at the first one
+        assert_eq!(builder.write_sqlite(&conn).unwrap(), 9);
+        assert_eq!(row(&conn, "O-O"), Some([1, 1, 0, 0, 0]));
+        assert_eq!(row(&conn, "-0"), None);
+    }
+}
+
+#[cfg(test)]
+mod phase {
+    use crate::engine::utils::phase::{
+        material_phase, taper_centipawns, GamePhase, MAX_MATERIAL_PHASE,
+    };
+    use chess_backend::{init, Board, Pieces};
+
+    fn phase_of(current_phase: Option<GamePhase>, fen: &str) -> GamePhase {
+        let board = Board::from(fen);
+        GamePhase::determine_phase(
+            current_phase,
+            &board,
+            &Pieces::from(board.base.white),
+            &Pieces::from(board.base.black),
+        )
+    }
+
+    #[test]
+    fn opening_ends() {
+        init();
+        let start = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1";
+        assert!(matches!(phase_of(None, start), GamePhase::Opening(None)));
+        assert!(matches!(
+            phase_of(Some(GamePhase::Opening(Some(1))), start),
+            GamePhase::Opening(Some(1))
+        ));
+
+        // Both sides developed and castled
+        assert!(matches!(
+            phase_of(
+                Some(GamePhase::Opening(None)),
+                "r2q1rk1/ppp2ppp/2np1n2/2b1p1B1/2B1P1b1/2NP1N2/PPP2PPP/R2Q1RK1 w - - 0 8"
+            ),
+            GamePhase::MiddleGame
+        ));
+        // Still able to castle
+        assert!(matches!(
+            phase_of(
+                Some(GamePhase::Opening(None)),
+                "r2qk2r/ppp2ppp/2np1n2/2b1p1B1/2B1P1b1/2NP1N2/PPP2PPP/R2QK2R w KQkq - 0 8"
+            ),
+            GamePhase::Opening(None)
+        ));
+        // Most pieces exchanged
+        assert!(matches!(
+            phase_of(
+                Some(GamePhase::Opening(Some(1))),
+                "4k3/pppp4/8/8/8/8/PPPP4/R3K3 w Q - 0 1"
+            ),
+            GamePhase::EndGame
+        ));
+    }
+
+    #[test]
+    fn tapering() {
+        init();
+        let pieces = |fen| {
+            let board = Board::from(fen);
+            material_phase(
+                &Pieces::from(board.base.white),
+                &Pieces::from(board.base.black),
+            )
+        };
+        assert_eq!(
+            pieces("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1"),
+            MAX_MATERIAL_PHASE
         );
+        assert_eq!(pieces("4k3/pppp4/8/8/8/8/PPPP4/R3K3 w Q - 0 1"), 2);
+        assert_eq!(pieces("4k3/8/8/8/8/8/8/4K3 w - - 0 1"), 0);
+
+        assert_eq!(taper_centipawns(100, 300, MAX_MATERIAL_PHASE), 100);
+        assert_eq!(taper_centipawns(100, 300, 0), 300);
+        assert_eq!(taper_centipawns(100, 300, MAX_MATERIAL_PHASE / 2), 200);
+    }
+}
+
+#[cfg(test)]
+mod pawn_structure {
+    use crate::engine::heuristics::pawn_structure::evaluate;
+    use chess_backend::{init, Board, Pieces};
+
+    fn pawns(fen: &str) -> i32 {
+        let board = Board::from(fen);
+        evaluate(
+            &Pieces::from(board.base.white),
+            &Pieces::from(board.base.black),
+            0,
+        )
+    }
+
+    #[test]
+    fn structure() {
+        init();
+        assert_eq!(
+            pawns("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1"),
+            0
+        );
+
+        // Doubled and isolated c-pawns against a healthy majority
+        assert!(pawns("4k3/ppp5/8/8/8/2P5/2P5/4K3 w - - 0 1") < 0);
+        // The same structure with colours swapped
+        assert_eq!(
+            pawns("4k3/2p5/2p5/8/8/8/PPP5/4K3 w - - 0 1"),
+            -pawns("4k3/ppp5/8/8/8/2P5/2P5/4K3 w - - 0 1")
+        );
+
+        // Passed pawns are worth more the further they are, and less when blocked
+        let passer = pawns("4k3/8/8/3P4/8/8/8/4K3 w - - 0 1");
+        assert!(passer > 0);
+        assert!(pawns("4k3/8/3P4/8/8/8/8/4K3 w - - 0 1") > passer);
+        assert!(pawns("4k3/8/3n4/3P4/8/8/8/4K3 w - - 0 1") < passer);
+        // Cached results give the same value
+        assert_eq!(pawns("4k3/8/8/3P4/8/8/8/4K3 w - - 0 1"), passer);
+    }
+}
+
+#[cfg(test)]
+mod king_safety {
+    use crate::engine::{heuristics::king_safety::evaluate, utils::phase::MAX_MATERIAL_PHASE};
+    use chess_backend::{init, Board, Pieces};
+
+    fn safety(fen: &str, material_phase: i32) -> i32 {
+        let board = Board::from(fen);
+        evaluate(
+            &Pieces::from(board.base.white),
+            &Pieces::from(board.base.black),
+            material_phase,
+        )
+    }
+
+    #[test]
+    fn king_danger() {
+        init();
+        let castled = "r1bq1rk1/pppp1ppp/2n2n2/2b1p3/2B1P3/2N2N2/PPPP1PPP/R1BQ1RK1 w - - 0 1";
+        assert_eq!(safety(castled, MAX_MATERIAL_PHASE), 0);
+
+        // White's king stuck in the centre on an open file
+        let exposed = "r1bq1rk1/pppp1ppp/2n2n2/2b5/2B5/2N2N2/PPP2PPP/R1BQK2R w KQ - 0 1";
+        assert!(safety(exposed, MAX_MATERIAL_PHASE) < 0);
+
+        // Queen and rook bearing down on a king without its shield
+        let attacked = "6k1/5ppp/8/8/8/8/3q1r2/6K1 w - - 0 1";
+        assert!(safety(attacked, MAX_MATERIAL_PHASE) < safety(exposed, MAX_MATERIAL_PHASE));
+
+        // None of it matters once the material is gone
+        assert_eq!(safety(attacked, 0), 0);
+    }
+}
+
+#[cfg(test)]
+mod mobility {
+    use crate::engine::{
+        heuristics::mobility::evaluate,
+        utils::{bitboard::pawn_attacks, phase::MAX_MATERIAL_PHASE},
+    };
+    use chess_backend::{init, Board, Colour, Pieces};
+
+    fn mobility(fen: &str) -> i32 {
+        let board = Board::from(fen);
+        evaluate(
+            &Pieces::from(board.base.white),
+            &Pieces::from(board.base.black),
+            MAX_MATERIAL_PHASE,
+        )
+    }
+
+    #[test]
+    fn piece_mobility() {
+        init();
+        assert_eq!(
+            mobility("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1"),
+            0
+        );
+        // An open diagonal against a bishop shut in by its own pawns
+        assert!(mobility("4k3/8/8/8/3b4/8/4P1P1/4KB2 w - - 0 1") < 0);
+        // A knight in the corner against one in the centre
+        assert!(mobility("n3k3/8/8/8/3N4/8/8/4K3 w - - 0 1") > 0);
+
+        // Squares guarded by pawns
+        assert_eq!(pawn_attacks(1 << 8, Colour::White), 1 << 17);
+        assert_eq!(pawn_attacks(1 << 55, Colour::Black), 1 << 46);
+        assert_eq!(pawn_attacks(1 << 12, Colour::White), 1 << 19 | 1 << 21);
+    }
+}
+
+#[cfg(test)]
+mod see {
+    use crate::engine::{
+        heuristics::see::{see, see_ge},
+        utils::lan::LanMove,
+    };
+    use chess_backend::{init, Board};
+
+    fn exchange(fen: &str, mv: &str) -> i32 {
+        see(&Board::from(fen), mv.parse::<LanMove>().unwrap())
+    }
+
+    #[test]
+    fn exchanges() {
+        init();
+        // An undefended pawn
+        assert_eq!(
+            exchange("1k1r4/1pp4p/p7/4p3/8/P5P1/1PP4P/2K1R3 w - - 0 1", "e1e5"),
+            100
+        );
+        // A pawn defended by a pawn, traded off
+        assert_eq!(exchange("4k3/8/2p5/3p4/4P3/8/8/4K3 w - - 0 1", "e4d5"), 0);
+        // The queen for a defended pawn
+        assert_eq!(exchange("4k3/8/2p5/3p4/8/8/8/3QK3 w - - 0 1", "d1d5"), -800);
+        // Both sides bring in pieces from behind, and black comes out a knight for a pawn ahead
+        assert_eq!(
+            exchange(
+                "1k1r3q/1ppn3p/p4b2/4p3/8/P2N2P1/1PP1R1BP/2K1Q3 w - - 0 1",
+                "d3e5"
+            ),
+            -200
+        );
+        // The queen behind the rook wins back a pawn
+        assert_eq!(
+            exchange("4k3/8/2p5/3p4/8/8/3R4/3QK3 w - - 0 1", "d2d5"),
+            -300
+        );
+    }
+
+    #[test]
+    fn special_moves() {
+        init();
+        // En passant
+        assert_eq!(exchange("4k3/8/8/3pP3/8/8/8/4K3 w - d6 0 1", "e5d6"), 100);
+        // Capturing a rook with a promotion, and losing the new queen to the king
+        assert_eq!(exchange("3rk3/2P5/8/8/8/8/8/4K3 w - - 0 1", "c7d8q"), 400);
+        // The king recaptures when nothing defends the square any more
+        assert_eq!(exchange("3rk3/8/8/8/8/8/3p4/3RK3 w - - 0 1", "d1d2"), 100);
+        // But not into check
+        assert_eq!(
+            exchange("3rk3/3r4/8/8/8/8/3p4/3RK3 w - - 0 1", "d1d2"),
+            -400
+        );
+
+        let board = Board::from("4k3/8/2p5/3p4/8/8/8/3QK3 w - - 0 1");
+        let mv = "d1d5".parse::<LanMove>().unwrap();
+        assert!(see_ge(&board, mv, -800));
+        assert!(!see_ge(&board, mv, 0));
+    }
+}
+
+#[cfg(test)]
+mod move_ordering {
+    use crate::engine::{move_ordering::OrderingTables, tree::Node, utils::lan::LanMove};
+    use chess_backend::{init, Board};
+
+    fn lan(mv: &str) -> LanMove {
+        mv.parse().unwrap()
+    }
+
+    #[test]
+    fn hash_move_captures_killers_history() {
+        init();
+        // Pawn c4 can take the rook on b5 or the queen on d5
+        let board = Board::from("4k3/8/8/1r1q4/2P1N3/8/8/4K3 w - - 0 1");
+        let mut children = Node::from_parent(board, None).generate_children();
+
+        let mut tables = OrderingTables::default();
+        tables.record_cutoff(lan("e4c3"), 0, 1);
+        // A cutoff at another ply only counts for the history
+        tables.record_cutoff(lan("e4g5"), 5, 3);
+        tables.order(&board, &mut children, Some(lan("e1f1")), 0);
+
+        let order: Vec<LanMove> = children.iter().take(5).filter_map(|c| c.lan).collect();
+        assert_eq!(
+            order,
+            ["e1f1", "c4d5", "c4b5", "e4c3", "e4g5"].map(lan).to_vec()
+        );
+    }
+}
+
+#[cfg(test)]
+mod quiescence {
+    use std::sync::atomic::AtomicBool;
+
+    use crate::engine::{tree::Node, utils::eval::Eval};
+    use chess_backend::{init, Board, Colour};
+
+    // The static evaluation and the one once captures are resolved
+    fn evals(fen: &str) -> (Eval, Eval) {
+        let board = Board::from(fen);
+        let maximize = board.side_to_move() == Colour::White;
+        let mut node = Node::from_parent(board, None);
+        let stand_pat = node.clone().eval_heuristic();
+        let resolved = node.quiescence(
+            0,
+            Eval::NEG_INFINITY,
+            Eval::INFINITY,
+            maximize,
+            &AtomicBool::new(false),
+        );
+        (stand_pat, resolved)
+    }
+
+    #[test]
+    fn hanging_pieces_are_taken() {
+        init();
+        // The black queen can be taken by a pawn
+        let (stand_pat, resolved) = evals("4k3/8/8/3q4/4P3/8/8/4K3 w - - 0 1");
+        assert!(stand_pat < Eval::centipawns(-500));
+        assert!(resolved > Eval::centipawns(0));
+
+        // And the other way around
+        let (stand_pat, resolved) = evals("4k3/8/4p3/3Q4/8/8/8/4K3 b - - 0 1");
+        assert!(stand_pat > Eval::centipawns(500));
+        assert!(resolved < Eval::centipawns(0));
+    }
+
+    #[test]
+    fn defended_pieces_are_left() {
+        init();
+        // Taking the pawn on d5 loses the queen to the pawn on c6
+        let (stand_pat, resolved) = evals("4k3/8/2p5/3p4/8/8/8/3QK3 w - - 0 1");
+        assert_eq!(resolved, stand_pat);
+    }
+}
+
+#[cfg(test)]
+mod mate_search {
+    use std::sync::atomic::AtomicBool;
+
+    use crate::engine::{
+        mate_search::{find_mate, MateResult},
+        utils::lan::LanMove,
+    };
+    use chess_backend::{init, Board};
+
+    #[test]
+    fn finds_mates() {
+        init();
+        let stop = AtomicBool::new(false);
+        let back_rank = Board::from("6k1/5ppp/8/8/8/8/8/R5K1 w - - 0 1");
+        assert_eq!(
+            find_mate(&back_rank, 3, &stop),
+            MateResult::Mate(vec!["a1a8".parse::<LanMove>().unwrap()])
+        );
+
+        // Two rooks need a quiet move first
+        let ladder = Board::from("7k/8/8/8/8/8/R7/1R4K1 w - - 0 1");
+        assert_eq!(find_mate(&ladder, 1, &stop), MateResult::NoMate);
+        let result = find_mate(&ladder, 3, &stop);
+        assert_eq!(result.moves(), Some(2));
+        let MateResult::Mate(line) = result else {
+            unreachable!()
+        };
+        assert_eq!(line[1], "h8g8".parse::<LanMove>().unwrap());
+        let mated = line
+            .iter()
+            .fold(ladder, |board, mv| mv.apply(&board).unwrap());
+        assert!(mated.generate_legal_moves().is_empty());
+    }
+
+    #[test]
+    fn proves_no_mate() {
+        init();
+        let stop = AtomicBool::new(false);
+        let bare_kings = Board::from("4k3/8/8/8/8/8/8/4K3 w - - 0 1");
+        assert_eq!(find_mate(&bare_kings, 3, &stop), MateResult::NoMate);
+        // Stalemated, there is nothing to mate with
+        let stalemate = Board::from("7k/8/5KQ1/8/8/8/8/8 b - - 0 1");
+        assert_eq!(find_mate(&stalemate, 2, &stop), MateResult::NoMate);
+
+        let stopped = AtomicBool::new(true);
+        let ladder = Board::from("7k/8/8/8/8/8/R7/1R4K1 w - - 0 1");
+        assert_eq!(find_mate(&ladder, 3, &stopped), MateResult::Stopped);
+    }
+}
+
+#[cfg(test)]
+mod smp {
+    use crate::engine::smp::skips_depth;
+
+    #[test]
+    fn staggered_depths() {
+        // The main thread searches every depth
+        assert!((1..20).all(|depth| !skips_depth(0, depth)));
+
+        // Helpers skip some depths, but never too many in a row
+        for thread_id in 1..=20 {
+            let searched: Vec<usize> = (1..40).filter(|d| !skips_depth(thread_id, *d)).collect();
+            assert!(searched.len() < 39);
+            assert!(searched.windows(2).all(|w| w[1] - w[0] <= 5));
+        }
+
+        // The first two helpers split the depths between them
+        assert_ne!(skips_depth(1, 4), skips_depth(2, 4));
+    }
+}
+
+#[cfg(test)]
+mod search {
+    use std::time::{Duration, Instant};
+
+    use chess_backend::{init, Board};
+
+    use crate::engine::{EngineController, SearchLimits};
+
+    fn controller(fen: &str) -> EngineController {
+        let mut controller = EngineController::builder()
+            .without_book()
+            .workers(2)
+            .build()
+            .unwrap();
+        controller.set_position(Board::from(fen));
+        controller
+    }
+
+    #[test]
+    fn stops_in_time() {
+        init();
+        let fen = "r1bqkb1r/pppp1ppp/2n2n2/4p3/2B1P3/5N2/PPPP1PPP/RNBQK2R w KQkq - 4 4";
+        let mut controller = controller(fen);
+        let start = Instant::now();
+        let res = controller.search(SearchLimits::time(Duration::from_millis(200)), |_| ());
+        assert!(start.elapsed() < Duration::from_secs(2));
+
+        let mv = res.best_move.expect("no move found");
+        assert!(mv.apply(&Board::from(fen)).is_some());
+        assert_eq!(res.pv.moves.first(), Some(&mv));
+    }
+
+    #[test]
+    fn reports_deeper_iterations() {
+        init();
+        let mut controller = controller("4k3/8/8/8/8/8/4P3/4K3 w - - 0 1");
+        let limits = SearchLimits {
+            depth: Some(4),
+            ..SearchLimits::default()
+        };
+        let mut depths = Vec::new();
+        let res = controller.search(limits, |res| depths.push(res.depth));
+
+        assert!(res.best_move.is_some());
+        assert_eq!(res.depth, 4);
+        assert_eq!(depths.last(), Some(&4));
+        assert!(depths.windows(2).all(|w| w[0] < w[1]));
+    }
+}
+
+#[cfg(test)]
+mod uci {
+    use std::time::Duration;
+
+    use chess_backend::Colour;
+
+    use crate::{
+        engine::SearchLimits,
+        uci::{parse_go, parse_option},
+    };
+
+    fn go(command: &str, side: Colour) -> SearchLimits {
+        let (limits, mate) = parse_go(command.split_whitespace(), side);
+        assert_eq!(mate, None);
+        limits
+    }
+
+    fn budget(time_left: u64, increment: u64, moves_to_go: Option<u32>) -> Option<Duration> {
+        SearchLimits::from_clock(
+            Duration::from_millis(time_left),
+            Duration::from_millis(increment),
+            moves_to_go,
+        )
+        .time
+    }
+
+    #[test]
+    fn clock_of_the_side_to_move() {
+        let clock = "wtime 60000 btime 30000 winc 1000 binc 500";
+        assert_eq!(go(clock, Colour::White).time, budget(60000, 1000, None));
+        assert_eq!(go(clock, Colour::Black).time, budget(30000, 500, None));
+
+        let limits = go("btime 30000 wtime 60000 movestogo 10", Colour::Black);
+        assert_eq!(limits.time, budget(30000, 0, Some(10)));
+        assert_eq!(limits.depth, None);
+    }
+
+    #[test]
+    fn fixed_limits() {
+        // A fixed time per move overrides the clock, wherever it is given
+        let limits = go("wtime 60000 movetime 2000 winc 1000", Colour::White);
+        assert_eq!(limits.time, Some(Duration::from_millis(2000)));
+        let limits = go("movetime 2000 wtime 60000", Colour::White);
+        assert_eq!(limits.time, Some(Duration::from_millis(2000)));
+
+        let limits = go("depth 5 nodes 1000", Colour::White);
+        assert_eq!((limits.time, limits.depth), (None, Some(5)));
+
+        let (limits, mate) = parse_go("mate 3".split_whitespace(), Colour::Black);
+        assert_eq!((limits.time, limits.depth, mate), (None, None, Some(3)));
+    }
+
+    #[test]
+    fn infinite() {
+        for command in ["infinite", "wtime 60000 depth 5 mate 2 infinite"] {
+            let (limits, mate) = parse_go(command.split_whitespace(), Colour::White);
+            assert_eq!((limits.time, limits.depth, mate), (None, None, None));
+        }
+    }
+
+    #[test]
+    fn options() {
+        fn option(command: &str) -> Option<(&str, String)> {
+            parse_option(command.split_whitespace())
+        }
+        assert_eq!(
+            option("name BookPolicy value Best"),
+            Some(("BookPolicy", "Best".to_owned()))
+        );
+        // Values may contain spaces, which are kept as single spaces
+        assert_eq!(
+            option("name BookFile value /home/me/my  books/openings.db"),
+            Some(("BookFile", "/home/me/my books/openings.db".to_owned()))
+        );
+        assert_eq!(option("name BookLearning"), None);
+        assert_eq!(option("BookPolicy value Best"), None);
+    }
+}
+
+#[cfg(test)]
+mod cecp {
+    use std::time::Duration;
+
+    use crate::{cecp::Clock, engine::SearchLimits};
+
+    fn clock(commands: &[(&str, &str)]) -> Clock {
+        let mut clock = Clock::default();
+        for (command, args) in commands {
+            clock.update(command, args);
+        }
+        clock
+    }
+
+    fn budget(time_left: u64, increment: f64, moves_to_go: Option<u32>) -> Option<Duration> {
+        SearchLimits::from_clock(
+            Duration::from_secs(time_left),
+            Duration::from_secs_f64(increment),
+            moves_to_go,
+        )
+        .time
+    }
+
+    #[test]
+    fn level() {
+        let limits = clock(&[("level", "40 5 0")]).limits(0);
+        assert_eq!(limits.time, budget(300, 0., Some(40)));
+        assert_eq!(limits.depth, None);
+
+        // Minutes and seconds with a fractional increment, the whole game in one session
+        let limits = clock(&[("level", "0 2:30 1.5")]).limits(10);
+        assert_eq!(limits.time, budget(150, 1.5, None));
+
+        // Incomplete levels are ignored
+        let limits = clock(&[("level", "40 5 0"), ("level", "40 5")]).limits(0);
+        assert_eq!(limits.time, budget(300, 0., Some(40)));
+    }
+
+    #[test]
+    fn moves_to_go() {
+        let clock = clock(&[("level", "40 5 0")]);
+        assert_eq!(clock.limits(39).time, budget(300, 0., Some(1)));
+        // The next session starts with a full set of moves
+        assert_eq!(clock.limits(40).time, budget(300, 0., Some(40)));
+        assert_eq!(clock.limits(45).time, budget(300, 0., Some(35)));
+    }
+
+    #[test]
+    fn time_and_depth() {
+        // Centiseconds left on the engine's clock
+        let limits = clock(&[("level", "40 5 0"), ("time", "6000")]).limits(0);
+        assert_eq!(limits.time, budget(60, 0., Some(40)));
+
+        // A fixed time per move takes precedence over the clock
+        let limits = clock(&[("st", "5"), ("time", "6000")]).limits(0);
+        assert_eq!(limits.time, Some(Duration::from_secs(5)));
+
+        let limits = clock(&[("sd", "4")]).limits(0);
+        assert_eq!(limits.depth, Some(4));
+        assert_eq!(limits.time, budget(300, 0., Some(40)));
     }
 }
 
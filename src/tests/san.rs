@@ -1,5 +1,4 @@
 use chess_backend::{init, Board, Colour, Piece, SanMove};
-use std::{fmt::Display, time::SystemTime};
 
 #[test]
 fn basic_notation() {
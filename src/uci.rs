@@ -0,0 +1,288 @@
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+use chess_backend::{Board, Colour};
+
+use crate::{
+    engine::{
+        mate_search::MateResult,
+        opening_book::{BookPolicy, OpeningBook, MIN_FREQUENCY},
+        utils::{eval::Eval, lan::LanMove},
+        EngineController, SearchLimits, DB_PATH,
+    },
+    ENGINE_NAME,
+};
+
+const ENGINE_AUTHOR: &str = "HellFelix";
+
+/// Runs the UCI protocol on the given input lines until the GUI sends `quit`.
+pub fn run(input: impl Iterator<Item = String>, controller: EngineController) {
+    let mut session = Session::new(controller);
+    for line in input {
+        if !session.handle(&line) {
+            break;
+        }
+    }
+    session.stop_search();
+}
+
+struct Session {
+    // The controller is moved to the search thread while a search is running
+    controller: Option<EngineController>,
+    search: Option<JoinHandle<EngineController>>,
+    stop: Arc<AtomicBool>,
+}
+impl Session {
+    fn new(controller: EngineController) -> Self {
+        Self {
+            stop: controller.stop_flag(),
+            controller: Some(controller),
+            search: None,
+        }
+    }
+
+    /// Handles a single line of input. Returns false once the session should end.
+    fn handle(&mut self, line: &str) -> bool {
+        let mut tokens = line.split_whitespace();
+        match tokens.next() {
+            Some("uci") => {
+                println!("id name {ENGINE_NAME} {}", env!("CARGO_PKG_VERSION"));
+                println!("id author {ENGINE_AUTHOR}");
+                println!(
+                    "option name BookPolicy type combo default Best var Best var Frequency var Eval var Uniform"
+                );
+                // 0 leaves the book choices unseeded
+                println!("option name BookSeed type spin default 0 min 0 max 2147483647");
+                println!("option name BookFile type string default {DB_PATH}");
+                println!("option name PolyglotBook type string default <empty>");
+                println!("option name BookByPosition type check default false");
+                println!("option name BookLearning type check default false");
+                println!("uciok");
+            }
+            Some("isready") => println!("readyok"),
+            Some("ucinewgame") => self.controller().new_game(),
+            Some("setoption") => self.set_option(tokens),
+            Some("position") => self.position(tokens),
+            Some("go") => self.go(tokens),
+            Some("stop") => self.stop_search(),
+            Some("quit") => return false,
+            // Unknown commands are ignored as required by the protocol
+            _ => (),
+        }
+        true
+    }
+
+    /// Waits for a running search to finish and hands back the controller.
+    fn controller(&mut self) -> &mut EngineController {
+        if let Some(search) = self.search.take() {
+            self.controller = Some(search.join().expect("Search thread panicked"));
+        }
+        self.controller
+            .as_mut()
+            .expect("Controller is available when no search is running")
+    }
+
+    fn stop_search(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        // An infinite search waits for the stop before it reports its move
+        if let Some(search) = &self.search {
+            search.thread().unpark();
+        }
+        self.controller();
+    }
+
+    fn set_option<'a>(&mut self, tokens: impl Iterator<Item = &'a str>) {
+        let Some((name, value)) = parse_option(tokens) else {
+            return;
+        };
+
+        let controller = self.controller();
+        match (name, value.as_str()) {
+            ("BookPolicy", "Best") => controller.set_book_policy(BookPolicy::BestByFrequency),
+            ("BookPolicy", "Frequency") => {
+                controller.set_book_policy(BookPolicy::FrequencyWeighted)
+            }
+            ("BookPolicy", "Eval") => controller.set_book_policy(BookPolicy::EvalWeighted),
+            ("BookPolicy", "Uniform") => {
+                controller.set_book_policy(BookPolicy::UniformAbove(MIN_FREQUENCY))
+            }
+            ("BookSeed", seed) => match seed.parse::<u64>() {
+                Ok(0) | Err(_) => (),
+                Ok(seed) => controller.seed_book(seed),
+            },
+            ("BookByPosition", enabled) => {
+                if let Err(e) = controller.set_book_position_lookup(enabled == "true") {
+                    println!("info string failed to index the book: {e}");
+                }
+            }
+            ("BookLearning", enabled) => {
+                if let Err(e) = controller.set_book_learning(enabled == "true") {
+                    println!("info string failed to prepare the book for learning: {e}");
+                }
+            }
+            ("BookFile", "<empty>") => controller.set_book(OpeningBook::empty()),
+            ("BookFile", path) => match OpeningBook::open(path) {
+                Ok(book) => controller.set_book(book),
+                Err(e) => println!("info string failed to open {path}: {e}"),
+            },
+            ("PolyglotBook", "<empty>") => (),
+            ("PolyglotBook", path) => match OpeningBook::open_polyglot(path) {
+                Ok(book) => controller.set_book(book),
+                Err(e) => println!("info string failed to open {path}: {e}"),
+            },
+            _ => println!("info string unknown option {name}"),
+        }
+    }
+
+    fn position<'a>(&mut self, mut tokens: impl Iterator<Item = &'a str>) {
+        let controller = self.controller();
+        match tokens.next() {
+            Some("startpos") => controller.set_start_position(),
+            Some("fen") => {
+                let fen: Vec<&str> = tokens.by_ref().take_while(|t| *t != "moves").collect();
+                controller.set_position(Board::from(fen.join(" ").as_str()));
+            }
+            _ => return,
+        }
+
+        for token in tokens.skip_while(|t| *t == "moves") {
+            match token.parse::<LanMove>() {
+                Ok(mv) if controller.make_move(mv) => (),
+                _ => {
+                    println!("info string illegal move {token}");
+                    return;
+                }
+            }
+        }
+    }
+
+    fn go<'a>(&mut self, tokens: impl Iterator<Item = &'a str>) {
+        self.controller();
+        let mut controller = self.controller.take().unwrap();
+        let side = controller.board().side_to_move();
+        let (mut limits, mate) = parse_go(tokens, side);
+        let infinite = limits.time.is_none() && limits.depth.is_none() && mate.is_none();
+        // Cleared here rather than in the search thread, so that a stop sent right away is kept
+        self.stop.store(false, Ordering::Relaxed);
+        let stop = self.stop.clone();
+
+        self.search = Some(thread::spawn(move || {
+            // Without a mate a move is still owed, a shallow search is enough to pick one
+            match mate {
+                Some(0) => {
+                    println!("info string mate 0 is not a search");
+                    limits.depth = Some(1);
+                }
+                Some(max_moves) => {
+                    let result = controller.find_mate(max_moves);
+                    match &result {
+                        MateResult::Mate(line) => {
+                            let pv: Vec<String> = line.iter().map(LanMove::to_string).collect();
+                            // Unwrap is safe, a mate was found
+                            let moves = result.moves().unwrap();
+                            println!("info score mate {moves} pv {}", pv.join(" "));
+                            println!("bestmove {}", line[0]);
+                            return controller;
+                        }
+                        MateResult::NoMate => {
+                            println!("info string no mate in {max_moves}");
+                            limits.depth = Some(1);
+                        }
+                        MateResult::Stopped => limits.depth = Some(1),
+                    }
+                }
+                None => (),
+            }
+
+            let res = controller.search(limits, |res| {
+                let pv = &res.pv;
+                if let Some(eval) = pv.eval {
+                    println!(
+                        "info depth {} seldepth {} score {} nodes {} nps {} time {} pv {}",
+                        pv.depth,
+                        pv.seldepth,
+                        score(eval, side),
+                        pv.nodes,
+                        pv.nps(),
+                        pv.time.as_millis(),
+                        pv.uci()
+                    );
+                }
+            });
+            // The search may run out of depth, but the move is only wanted once the GUI stops it
+            while infinite && !stop.load(Ordering::Relaxed) {
+                thread::park();
+            }
+            match res.best_move {
+                Some(mv) => println!("bestmove {mv}"),
+                None => println!("bestmove 0000"),
+            }
+            controller
+        }));
+    }
+}
+
+// The evaluation from the point of view of the side to move, as a UCI score
+fn score(eval: Eval, side: Colour) -> String {
+    match (eval.mate_in(side), side) {
+        (Some(moves), _) => format!("mate {moves}"),
+        (None, Colour::White) => format!("cp {}", eval.value()),
+        (None, Colour::Black) => format!("cp {}", -eval.value()),
+    }
+}
+
+// setoption name <id> value <x>, where the value may contain spaces
+pub(crate) fn parse_option<'a>(tokens: impl Iterator<Item = &'a str>) -> Option<(&'a str, String)> {
+    let tokens: Vec<&str> = tokens.collect();
+    match tokens.as_slice() {
+        ["name", name, "value", value @ ..] => Some((name, value.join(" "))),
+        _ => None,
+    }
+}
+
+// The limits of the search, and the number of moves to look for a mate in if that was asked for
+pub(crate) fn parse_go<'a>(
+    mut tokens: impl Iterator<Item = &'a str>,
+    side: Colour,
+) -> (SearchLimits, Option<usize>) {
+    let mut limits = SearchLimits::default();
+    let mut mate = None;
+    let mut time_left = None;
+    let mut increment = Duration::ZERO;
+    let mut moves_to_go = None;
+
+    while let Some(token) = tokens.next() {
+        let mut value = || tokens.next().and_then(|v| v.parse::<u64>().ok());
+        match (token, side) {
+            ("wtime", Colour::White) | ("btime", Colour::Black) => {
+                time_left = value().map(Duration::from_millis)
+            }
+            ("winc", Colour::White) | ("binc", Colour::Black) => {
+                increment = value().map_or(Duration::ZERO, Duration::from_millis)
+            }
+            ("movestogo", _) => moves_to_go = value().map(|n| n as u32),
+            ("movetime", _) => limits.time = value().map(Duration::from_millis),
+            ("depth", _) => limits.depth = value().map(|d| d as usize),
+            ("mate", _) => mate = value().map(|n| n as usize),
+            ("infinite", _) => return (SearchLimits::default(), None),
+            // The opponent's clock and unsupported limits
+            ("wtime" | "btime" | "winc" | "binc" | "nodes", _) => {
+                value();
+            }
+            _ => (),
+        }
+    }
+
+    if limits.time.is_none() {
+        if let Some(time_left) = time_left {
+            limits.time = SearchLimits::from_clock(time_left, increment, moves_to_go).time;
+        }
+    }
+    (limits, mate)
+}